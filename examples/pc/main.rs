@@ -26,9 +26,28 @@ fn main() {
     }
 
     let hw = hardware::Hardware::new();
+    let sample_rate = hw.sample_rate();
 
     let rom = read_rom(&args[1]);
 
-    let mut nes = Nes::new(&rom, hw);
+    let mut nes = match Nes::new(&rom, hw) {
+        Ok(nes) => nes,
+        Err(err) => {
+            eprintln!("{}: {}", args[1], err);
+            std::process::exit(1);
+        }
+    };
+
+    nes.set_sample_rate(sample_rate);
+
+    let sav = std::path::Path::new(&args[1]).with_extension("sav");
+    if let Ok(data) = std::fs::read(&sav) {
+        nes.load_battery_ram(&data);
+    }
     while nes.step() {}
+    if nes.battery_dirty() {
+        if let Some(ram) = nes.battery_ram() {
+            std::fs::write(&sav, &*ram).expect("failed to write save file");
+        }
+    }
 }
@@ -0,0 +1,199 @@
+use core::f32::consts::PI;
+
+use alloc::{vec, vec::Vec};
+
+use crate::math::sin_cos;
+
+const PHASES: usize = 64;
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+// Fraction of the output Nyquist frequency the kernel lets through.
+const CUTOFF: f32 = 0.9;
+
+// The console's output stage: two first-order high-passes and a low-pass.
+const HIGH_PASS_1: f32 = 90.0;
+const HIGH_PASS_2: f32 = 440.0;
+const LOW_PASS: f32 = 14000.0;
+
+struct HighPass {
+    alpha: f32,
+    input: f32,
+    output: f32,
+}
+
+impl HighPass {
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        Self {
+            alpha: rc / (rc + 1.0 / sample_rate),
+            input: 0.0,
+            output: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        self.output = self.alpha * (self.output + input - self.input);
+        self.input = input;
+        self.output
+    }
+}
+
+struct LowPass {
+    alpha: f32,
+    output: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Self {
+            alpha: dt / (rc + dt),
+            output: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        self.output += self.alpha * (input - self.output);
+        self.output
+    }
+}
+
+// Band-limited step synthesis: amplitude changes are recorded at clock
+// timestamps as windowed-sinc impulses, and integrating the result gives
+// alias-free steps at the output rate.
+pub struct BlipBuffer {
+    kernel: Vec<[f32; WIDTH]>,
+    // Output samples per input clock.
+    ratio: f64,
+    // Position of the current clock, in output samples from `buffer[0]`.
+    time: f64,
+    buffer: Vec<f32>,
+    level: f32,
+
+    high_pass: [HighPass; 2],
+    low_pass: LowPass,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        let mut kernel = vec![[0.0; WIDTH]; PHASES + 1];
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let center = (HALF_WIDTH - 1) as f32 + phase as f32 / PHASES as f32;
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f32 - center;
+                let sinc = if x == 0.0 {
+                    CUTOFF
+                } else {
+                    sin_cos(PI * x * CUTOFF).0 / (PI * x)
+                };
+                let window = 0.42
+                    + 0.5 * sin_cos(PI * x / HALF_WIDTH as f32).1
+                    + 0.08 * sin_cos(2.0 * PI * x / HALF_WIDTH as f32).1;
+                *tap = sinc * window;
+            }
+            // Each step must settle at exactly its delta.
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|tap| *tap /= sum);
+        }
+
+        let mut blip = Self {
+            kernel,
+            ratio: 0.0,
+            time: 0.0,
+            buffer: Vec::new(),
+            level: 0.0,
+            high_pass: [
+                HighPass::new(HIGH_PASS_1, 1.0),
+                HighPass::new(HIGH_PASS_2, 1.0),
+            ],
+            low_pass: LowPass::new(LOW_PASS, 1.0),
+        };
+        blip.set_rates(clock_rate, sample_rate);
+        blip
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: u32) {
+        self.ratio = sample_rate as f64 / clock_rate;
+        let rate = sample_rate as f32;
+        self.high_pass = [
+            HighPass::new(HIGH_PASS_1, rate),
+            HighPass::new(HIGH_PASS_2, rate),
+        ];
+        // Low output rates can't hold the low-pass corner below Nyquist.
+        self.low_pass = LowPass::new(LOW_PASS.min(rate / 2.0), rate);
+    }
+
+    // Records a change in amplitude at the current clock.
+    pub fn add_delta(&mut self, delta: f32) {
+        let index = self.time as usize;
+        let phase = ((self.time - index as f64) * PHASES as f64 + 0.5) as usize;
+        if self.buffer.len() < index + WIDTH {
+            self.buffer.resize(index + WIDTH, 0.0);
+        }
+        for (sample, tap) in self.buffer[index..].iter_mut().zip(&self.kernel[phase]) {
+            *sample += delta * tap;
+        }
+    }
+
+    pub fn clock(&mut self, clocks: u32) {
+        self.time += self.ratio * clocks as f64;
+    }
+
+    // Samples before the current clock can't be touched by later deltas.
+    pub fn available(&self) -> usize {
+        self.time as usize
+    }
+
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let count = self.available();
+        if self.buffer.len() < count {
+            self.buffer.resize(count, 0.0);
+        }
+        for delta in self.buffer.drain(..count) {
+            self.level += delta;
+            let [first, second] = &mut self.high_pass;
+            let sample = second.apply(first.apply(self.low_pass.apply(self.level)));
+            out.push(sample);
+        }
+        self.time -= count as f64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_count() {
+        let mut blip = BlipBuffer::new(1789773.0, 44100);
+        blip.clock(1789773 / 60);
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+        assert_eq!(out.len(), 734);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+
+        blip.clock(1789773 / 60);
+        blip.read_samples(&mut out);
+        assert_eq!(out.len(), 1469);
+    }
+
+    #[test]
+    fn test_step_response() {
+        let mut blip = BlipBuffer::new(1789773.0, 44100);
+        blip.clock(100);
+        blip.add_delta(0.5);
+        blip.clock(1789773 / 10);
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+
+        // The 440 Hz high-pass already takes a bite out of the edge before
+        // it pulls the level back to 0.
+        let peak = out.iter().cloned().fold(0.0, f32::max);
+        assert!(peak > 0.3 && peak < 0.5);
+        assert!(out[..HALF_WIDTH - 2]
+            .iter()
+            .all(|sample| sample.abs() < 0.05));
+        assert!(out.last().unwrap().abs() < 0.01);
+    }
+}
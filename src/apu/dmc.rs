@@ -0,0 +1,264 @@
+use super::ChannelState;
+use crate::{
+    region::Region,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+pub struct Dmc {
+    irq_enable: bool,
+    loop_flag: bool,
+    rates: &'static [u16; 16],
+    rate: u16,
+    timer: u16,
+    output: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    interrupt: bool,
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        let rates = region.dmc_rates();
+        Self {
+            irq_enable: false,
+            loop_flag: false,
+            rates,
+            rate: rates[0],
+            timer: rates[0],
+            output: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            interrupt: false,
+        }
+    }
+
+    pub fn update_1(&mut self, value: u8) {
+        self.irq_enable = value & 0x80 != 0;
+        self.loop_flag = value & 0x40 != 0;
+        self.rate = self.rates[(value & 0x0F) as usize];
+        if !self.irq_enable {
+            self.interrupt = false;
+        }
+    }
+
+    pub fn update_2(&mut self, value: u8) {
+        self.output = value & 0x7F;
+    }
+
+    pub fn update_3(&mut self, value: u8) {
+        self.sample_address = 0xC000 | (value as u16) << 6;
+    }
+
+    pub fn update_4(&mut self, value: u8) {
+        self.sample_length = (value as u16) << 4 | 1;
+    }
+
+    // $4015 bit 4: stop playback, or start the sample over if it had ended.
+    pub fn set_enable(&mut self, enable: bool) {
+        self.interrupt = false;
+        if !enable {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining != 0
+    }
+
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    // The address the memory reader wants next, if the buffer has emptied.
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining != 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn fill(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enable {
+                self.interrupt = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle; the rate table is in CPU cycles.
+    pub fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.output <= 125 {
+                    self.output += 2;
+                }
+            } else if self.output >= 2 {
+                self.output -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(value) => {
+                    self.silence = false;
+                    self.shift = value;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // The volume is the 7-bit output level and the length the bytes left.
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            period: self.rate,
+            volume: self.output,
+            duty: 0,
+            length: self.bytes_remaining,
+            active: self.is_active(),
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq_enable);
+        state.write_bool(self.loop_flag);
+        state.write_u16(self.rate);
+        state.write_u16(self.timer);
+        state.write_u8(self.output);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_bool(self.buffer.is_some());
+        state.write_u8(self.buffer.unwrap_or(0));
+        state.write_u8(self.shift);
+        state.write_u8(self.bits_remaining);
+        state.write_bool(self.silence);
+        state.write_bool(self.interrupt);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enable = state.read_bool()?;
+        self.loop_flag = state.read_bool()?;
+        self.rate = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.output = state.read_u8()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.current_address = state.read_u16()?;
+        self.bytes_remaining = state.read_u16()?;
+        let full = state.read_bool()?;
+        let buffer = state.read_u8()?;
+        self.buffer = full.then_some(buffer);
+        self.shift = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        self.silence = state.read_bool()?;
+        self.interrupt = state.read_bool()?;
+        if self.output > 0x7F
+            || !self.rates.contains(&self.rate)
+            || !(1..=8).contains(&self.bits_remaining)
+            || self.current_address < 0x8000
+        {
+            return Err(StateError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_output_unit() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.update_1(0x0F);
+        dmc.update_2(0x40);
+        assert_eq!(dmc.output(), 0x40);
+
+        dmc.update_4(0);
+        dmc.set_enable(true);
+        assert_eq!(dmc.fetch_address(), Some(0xC000));
+        dmc.fill(0b0000_1111);
+        assert_eq!(dmc.fetch_address(), None);
+        assert!(!dmc.is_active());
+
+        // The first byte starts once the silent byte already in the shifter
+        // has played out, the first bit of it still at the old rate.
+        for _ in 0..428 + 7 * 54 {
+            dmc.tick();
+        }
+        assert_eq!(dmc.output(), 0x40);
+        for _ in 0..4 * 54 {
+            dmc.tick();
+        }
+        assert_eq!(dmc.output(), 0x48);
+        for _ in 0..4 * 54 {
+            dmc.tick();
+        }
+        assert_eq!(dmc.output(), 0x40);
+    }
+
+    #[test]
+    fn test_loop_and_address_wrap() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.update_1(0xC0);
+        dmc.update_3(0xFF);
+        dmc.update_4(0);
+        dmc.set_enable(true);
+        assert_eq!(dmc.fetch_address(), Some(0xFFC0));
+        dmc.fill(0);
+        // Looping restarts instead of raising the IRQ.
+        assert!(dmc.is_active());
+        assert!(!dmc.interrupt());
+
+        dmc.update_1(0x00);
+        dmc.current_address = 0xFFFF;
+        dmc.bytes_remaining = 2;
+        dmc.buffer = None;
+        dmc.fill(0);
+        assert_eq!(dmc.fetch_address(), None);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+}
@@ -1,21 +1,24 @@
 use crate::{
     device::IOHandler,
-    memory::{MemoryBus, MemoryRead, MemoryWrite},
+    interrupt::{IrqLine, IrqSource},
+    memory::{Bus, MemoryBus, MemoryRead, MemoryWrite},
+    region::Region,
+    state::{Snapshot, StateError, StateReader, StateWriter},
 };
+use alloc::{boxed::Box, vec::Vec};
 use bitflags::bitflags;
-use libc_print::libc_println;
 
-pub use self::util::{Tone, WaveForm};
-use self::{noise::Noise, pulse::Pulse, triangle::Triangle};
+use self::{blip::BlipBuffer, dmc::Dmc, noise::Noise, pulse::Pulse, triangle::Triangle};
 
+mod blip;
+mod dmc;
 mod noise;
 mod pulse;
 mod triangle;
 mod util;
 
 const CLOCK_RATIO: usize = 1;
-const PITCH_RATIO: usize = 3;
-const CPU_CLOCK: f64 = 1789773.0 / CLOCK_RATIO as f64;
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
 
 bitflags! {
     pub struct TimeEvent: u8 {
@@ -27,48 +30,59 @@ bitflags! {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Expansion,
+}
+
+// The cartridge side of the mixer. The APU clocks it once per CPU cycle,
+// which is also the only clock the mapper gets, so expansion sound and M2
+// counters can't drift apart; it returns the output for that cycle.
+pub trait AudioHandler {
+    fn clock(&mut self) -> f32;
+}
+
+// A snapshot of one channel for visualizers. `period` is the raw timer
+// reload value and `length` what is left on the length counter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelState {
+    pub period: u16,
+    pub volume: u8,
+    pub duty: u8,
+    pub length: u16,
+    pub active: bool,
+}
+
 pub struct FrameCounter {
     timer: usize,
     mode5: bool,
     interrupt: bool,
+    region: Region,
 }
 
 impl FrameCounter {
-    pub fn new() -> Self {
+    pub fn new(region: Region) -> Self {
         Self {
             timer: 0,
             mode5: false,
             interrupt: false,
+            region,
         }
     }
 
     pub fn next(&mut self) -> TimeEvent {
         let mut event = TimeEvent::from_bits_truncate(0x00);
-        self.timer = (self.timer + 1)
-            % (if self.mode5 {
-                37281 * CLOCK_RATIO
-            } else {
-                29830 * CLOCK_RATIO
-            });
-
-        let time_table = if self.mode5 {
-            [
-                7457 * CLOCK_RATIO,
-                14913 * CLOCK_RATIO,
-                22371 * CLOCK_RATIO,
-                37281 * CLOCK_RATIO,
-            ]
-        } else {
-            [
-                7457 * CLOCK_RATIO,
-                14913 * CLOCK_RATIO,
-                22371 * CLOCK_RATIO,
-                29829 * CLOCK_RATIO,
-            ]
-        };
+        let (steps, length) = self.region.frame_counter(self.mode5);
+        self.timer = (self.timer + 1) % (length * CLOCK_RATIO);
+        let time_table = steps.map(|step| step * CLOCK_RATIO);
 
         event.set(TimeEvent::CPUClock, self.timer % CLOCK_RATIO == 0);
-        event.set(TimeEvent::APUClock, self.timer % CLOCK_RATIO * 2 == 0);
+        event.set(TimeEvent::APUClock, self.timer % (CLOCK_RATIO * 2) == 0);
         event.set(TimeEvent::QuarterFrame, time_table.contains(&self.timer));
         event.set(
             TimeEvent::HalfFrame,
@@ -91,81 +105,208 @@ impl FrameCounter {
     }
 }
 
+impl Snapshot for FrameCounter {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u16(self.timer as u16);
+        state.write_bool(self.mode5);
+        state.write_bool(self.interrupt);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.timer = state.read_u16()? as usize;
+        self.mode5 = state.read_bool()?;
+        self.interrupt = state.read_bool()?;
+        Ok(())
+    }
+}
+
 pub struct Apu {
     pulse_1: Pulse,
     pulse_2: Pulse,
     triangle: Triangle,
     noise: Noise,
+    dmc: Dmc,
+    expansion: Box<dyn AudioHandler>,
+    expansion_level: f32,
     ctrl: u8,
     status: u8,
     frame_counter: FrameCounter,
     cpu_cycles: usize,
+    irq: IrqLine,
+    region: Region,
+    // CPU cycles the DMC has stolen for sample fetches, not yet paid.
+    stall: usize,
+
+    // Per-channel gain in the mixer, indexed by `Channel`; these are user
+    // settings and aren't part of the saved state.
+    volume: [f32; 6],
+    muted: [bool; 6],
+    solo: [bool; 6],
+
+    // The mixer level as of the last cycle; changes go into the blip buffer.
+    level: f32,
+    blip: BlipBuffer,
 }
 
 impl Apu {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(irq: IrqLine, region: Region, expansion: Box<dyn AudioHandler>) -> Self {
+        let mut apu = Self {
             pulse_1: Pulse::new(1),
             pulse_2: Pulse::new(0),
             triangle: Triangle::new(),
-            noise: Noise::new(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            expansion,
+            expansion_level: 0.0,
             ctrl: 0,
             status: 0,
-            frame_counter: FrameCounter::new(),
+            frame_counter: FrameCounter::new(region),
             cpu_cycles: 0,
-        }
+            irq,
+            region,
+            stall: 0,
+            volume: [1.0; 6],
+            muted: [false; 6],
+            solo: [false; 6],
+            level: 0.0,
+            blip: BlipBuffer::new(region.cpu_clock(), DEFAULT_SAMPLE_RATE),
+        };
+        // Channels idle at a non-zero level; that isn't an edge to play.
+        apu.level = apu.mix();
+        apu
     }
 
-    pub fn step(&mut self, cpu_cycle: u16) -> [Tone; 4] {
-        // let apu_cycles = (cpu_cycle as usize + self.cpu_cycles & 0x01) / 2;/
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.blip.set_rates(self.region.cpu_clock(), sample_rate);
+    }
+
+    pub fn step(&mut self, mmu: &MemoryBus, cpu_cycle: u16) {
         self.cpu_cycles += cpu_cycle as usize;
 
         for _ in 0..cpu_cycle {
             let event = self.frame_counter.next();
-            if !self.pulse_1.is_halt() {
-                self.pulse_1.tick(&event);
-            }
-            if !self.pulse_2.is_halt() {
-                self.pulse_2.tick(&event);
-            }
-            if !self.triangle.is_halt() {
-                self.triangle.tick(&event);
-            }
-            if !self.noise.is_halt() {
-                self.noise.tick(&event);
+            self.clock(&event);
+            self.dmc.tick();
+            self.expansion_level = self.expansion.clock();
+            if let Some(address) = self.dmc.fetch_address() {
+                // The CPU is halted for the read; usually four cycles.
+                self.dmc.fill(mmu.read_byte(address));
+                self.stall += 4;
             }
+            self.irq.set(IrqSource::Dmc, self.dmc.interrupt());
 
             if event.contains(TimeEvent::Interrupt) {
                 self.status |= 0x40;
+                self.irq.assert(IrqSource::FrameCounter);
+            }
+
+            let level = self.mix();
+            if level != self.level {
+                self.blip.add_delta(level - self.level);
+                self.level = level;
             }
+            self.blip.clock(1);
         }
+    }
+
+    fn clock(&mut self, event: &TimeEvent) {
+        self.pulse_1.tick(event);
+        self.pulse_2.tick(event);
+        self.triangle.tick(event);
+        self.noise.tick(event);
+    }
+
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volume[channel as usize] = volume;
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
 
-        if self.pulse_1.is_end() {
-            self.status |= 0b0000_0001;
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.solo[channel as usize] = solo;
+    }
+
+    fn gain(&self, channel: Channel) -> f32 {
+        let index = channel as usize;
+        let soloing = self.solo.contains(&true);
+        if self.muted[index] || (soloing && !self.solo[index]) {
+            0.0
         } else {
-            self.status &= 0b1111_1110;
+            self.volume[index]
         }
-        if self.pulse_2.is_end() {
-            self.status |= 0b0000_0010;
-        } else {
-            self.status &= 0b1111_1101;
+    }
+
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        match channel {
+            Channel::Pulse1 => self.pulse_1.state(),
+            Channel::Pulse2 => self.pulse_2.state(),
+            Channel::Triangle => self.triangle.state(),
+            Channel::Noise => self.noise.state(),
+            Channel::Dmc => self.dmc.state(),
+            // Expansion chips have too many voices to fit; only the mixer
+            // controls apply.
+            Channel::Expansion => ChannelState::default(),
         }
-        if self.triangle.is_end() {
-            self.status |= 0b0000_0100;
+    }
+
+    // The 2A03's non-linear DAC, as the usual rational approximations.
+    // Channel gains scale the levels going into it.
+    fn mix(&self) -> f32 {
+        let pulse = self.pulse_1.output() as f32 * self.gain(Channel::Pulse1)
+            + self.pulse_2.output() as f32 * self.gain(Channel::Pulse2);
+        let pulse_out = if pulse == 0.0 {
+            0.0
         } else {
-            self.status &= 0b1111_1011;
-        }
-        if self.noise.is_end() {
-            self.status |= 0b0000_1000;
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = self.triangle.output() as f32 * self.gain(Channel::Triangle) / 8227.0
+            + self.noise.output() as f32 * self.gain(Channel::Noise) / 12241.0
+            + self.dmc.output() as f32 * self.gain(Channel::Dmc) / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
         } else {
-            self.status &= 0b1111_0111;
-        }
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out + self.expansion_level * self.gain(Channel::Expansion)
+    }
+
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        self.blip.read_samples(out);
+    }
 
-        let p1_volume = self.pulse_1.value();
-        let p2_volume = self.pulse_2.value();
-        let tri_volume = self.triangle.value();
-        let noise_volume = self.noise.value();
-        [p1_volume, p2_volume, tri_volume, noise_volume]
+    pub fn take_stall(&mut self) -> usize {
+        core::mem::take(&mut self.stall)
+    }
+}
+
+impl Snapshot for Apu {
+    fn save(&self, state: &mut StateWriter) {
+        self.pulse_1.save(state);
+        self.pulse_2.save(state);
+        self.triangle.save(state);
+        self.noise.save(state);
+        self.dmc.save(state);
+        state.write_u8(self.ctrl);
+        state.write_u8(self.status);
+        self.frame_counter.save(state);
+        state.write_u64(self.cpu_cycles as u64);
+        state.write_u8(self.stall as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse_1.load(state)?;
+        self.pulse_2.load(state)?;
+        self.triangle.load(state)?;
+        self.noise.load(state)?;
+        self.dmc.load(state)?;
+        self.ctrl = state.read_u8()?;
+        self.status = state.read_u8()?;
+        self.frame_counter.load(state)?;
+        self.cpu_cycles = state.read_u64()? as usize;
+        self.stall = state.read_u8()? as usize;
+        Ok(())
     }
 }
 
@@ -173,8 +314,26 @@ impl IOHandler for Apu {
     fn read(&mut self, mmu: &MemoryBus, address: u16) -> MemoryRead {
         match address {
             0x4015 => {
-                let value = self.status;
+                let mut value = self.status & 0b0100_0000;
+                for (bit, active) in [
+                    self.pulse_1.is_active(),
+                    self.pulse_2.is_active(),
+                    self.triangle.is_active(),
+                    self.noise.is_active(),
+                ]
+                .into_iter()
+                .enumerate()
+                {
+                    value |= (active as u8) << bit;
+                }
+                if self.dmc.is_active() {
+                    value |= 0b0001_0000;
+                }
+                if self.dmc.interrupt() {
+                    value |= 0b1000_0000;
+                }
                 self.status &= 0b1011_1111;
+                self.irq.acknowledge(IrqSource::FrameCounter);
                 MemoryRead::Value(value)
             }
             0x4017 => MemoryRead::Pass,
@@ -248,32 +407,107 @@ impl IOHandler for Apu {
                 self.noise.update_4(value);
                 MemoryWrite::Value(value)
             }
-            0x4010 => MemoryWrite::Pass,
-            0x4011 => MemoryWrite::Pass,
-            0x4012 => MemoryWrite::Pass,
-            0x4013 => MemoryWrite::Pass,
+            0x4010 => {
+                self.dmc.update_1(value);
+                self.irq.set(IrqSource::Dmc, self.dmc.interrupt());
+                MemoryWrite::Value(value)
+            }
+            0x4011 => {
+                self.dmc.update_2(value);
+                MemoryWrite::Value(value)
+            }
+            0x4012 => {
+                self.dmc.update_3(value);
+                MemoryWrite::Value(value)
+            }
+            0x4013 => {
+                self.dmc.update_4(value);
+                MemoryWrite::Value(value)
+            }
             0x4015 => {
                 self.ctrl = value;
-                if value & 0x01 == 0 {
-                    self.pulse_1.disable();
-                }
-                if value & 0x02 == 0 {
-                    self.pulse_2.disable();
-                }
-                if value & 0x04 == 0 {
-                    self.triangle.disable();
-                }
-                if value & 0x08 == 0 {
-                    self.noise.disable();
-                }
+                self.pulse_1.set_enable(value & 0x01 != 0);
+                self.pulse_2.set_enable(value & 0x02 != 0);
+                self.triangle.set_enable(value & 0x04 != 0);
+                self.noise.set_enable(value & 0x08 != 0);
+                self.dmc.set_enable(value & 0x10 != 0);
+                self.irq.acknowledge(IrqSource::Dmc);
                 MemoryWrite::Value(value)
             }
             0x4017 => {
                 self.frame_counter
                     .set(value & 0b1000_0000 != 0, value & 0b0100_0000 != 0);
+                if value & 0b0100_0000 != 0 {
+                    self.status &= 0b1011_1111;
+                    self.irq.acknowledge(IrqSource::FrameCounter);
+                }
+                // Selecting the 5-step sequence clocks everything at once.
+                if value & 0b1000_0000 != 0 {
+                    self.clock(&(TimeEvent::QuarterFrame | TimeEvent::HalfFrame));
+                }
                 MemoryWrite::Value(value)
             }
             _ => MemoryWrite::Block,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A square wave at ~1.8 kHz, toggling every 500 clocks.
+    struct Square(usize);
+
+    impl AudioHandler for Square {
+        fn clock(&mut self) -> f32 {
+            self.0 += 1;
+            if self.0 / 500 % 2 == 0 {
+                0.0
+            } else {
+                0.2
+            }
+        }
+    }
+
+    fn expansion_peak(apu: &mut Apu, mmu: &MemoryBus) -> f32 {
+        let mut samples = Vec::new();
+        apu.step(mmu, 30000);
+        apu.drain_samples(&mut samples);
+        samples[400..]
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let mut counter = FrameCounter::new(region);
+            counter.set(true, true);
+            let (_, length) = region.frame_counter(true);
+            let (mut quarter, mut half) = (0, 0);
+            for _ in 0..length {
+                let event = counter.next();
+                quarter += event.contains(TimeEvent::QuarterFrame) as usize;
+                half += event.contains(TimeEvent::HalfFrame) as usize;
+                assert!(!event.contains(TimeEvent::Interrupt));
+            }
+            assert_eq!((quarter, half), (4, 2));
+        }
+    }
+
+    #[test]
+    fn test_expansion_mix() {
+        let mmu = MemoryBus::new();
+        let mut apu = Apu::new(IrqLine::new(), Region::Ntsc, Box::new(Square(0)));
+        let loud = expansion_peak(&mut apu, &mmu);
+        assert!(loud > 0.05);
+
+        apu.set_volume(Channel::Expansion, 0.5);
+        let quiet = expansion_peak(&mut apu, &mmu);
+        assert!(quiet < loud * 0.6 && quiet > loud * 0.4);
+
+        apu.set_muted(Channel::Expansion, true);
+        assert!(expansion_peak(&mut apu, &mmu) < 0.001);
+    }
+}
@@ -1,12 +1,9 @@
-use libc_print::libc_println;
-
 use super::util::*;
-use super::TimeEvent;
-use super::CPU_CLOCK;
-
-const NOISE_PERIOD_TABLE: [u16; 16] = [
-    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
-];
+use super::{ChannelState, TimeEvent};
+use crate::{
+    region::Region,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
 
 struct FeedbackRegister {
     register: u16,
@@ -29,28 +26,37 @@ impl FeedbackRegister {
         self.mode = mode;
     }
 
+    // The period table is in CPU cycles and the register is clocked at that
+    // rate.
     pub fn set_timer(&mut self, period: u16) {
         self.period = period;
-        self.counter = period;
     }
 
     pub fn tick(&mut self) {
         if self.counter == 0 {
-            self.counter = self.period;
+            self.counter = self.period.saturating_sub(1);
             let feedback = (self.register & 0x01)
                 ^ if self.mode {
-                    (self.register >> 5) & 0x01
+                    (self.register >> 6) & 0x01
                 } else {
                     (self.register >> 1) & 0x01
                 };
             self.register = (self.register >> 1) | feedback << 14;
         } else {
-            self.counter -= self.period;
+            self.counter -= 1;
         }
     }
 
     pub fn is_mute(&self) -> bool {
-        self.register & 0x01 == 0
+        self.register & 0x01 != 0
+    }
+
+    pub fn period(&self) -> u16 {
+        self.period
+    }
+
+    pub fn mode(&self) -> bool {
+        self.mode
     }
 }
 
@@ -60,22 +66,24 @@ pub struct Noise {
 
     feedback: FeedbackRegister,
     length: LengthCounter,
+    periods: &'static [u16; 16],
 }
 
 impl Noise {
-    pub fn new() -> Self {
+    pub fn new(region: Region) -> Self {
         Self {
             halt: false,
             envelope: Envelope::new(),
 
             feedback: FeedbackRegister::new(),
             length: LengthCounter::new(),
+            periods: region.noise_periods(),
         }
     }
 
     pub fn update_1(&mut self, value: u8) {
         self.halt = value & 0b0010_0000 != 0;
-        self.length.set_enable(!self.halt);
+        self.length.set_halt(self.halt);
         self.envelope.set_disable(value & 0b0001_0000 != 0);
         self.envelope.set_volume(value & 0x0F);
     }
@@ -85,7 +93,7 @@ impl Noise {
     pub fn update_3(&mut self, value: u8) {
         self.feedback.set_mode(value & 0x80 != 0);
         self.feedback
-            .set_timer(NOISE_PERIOD_TABLE[(value & 0x0F) as usize])
+            .set_timer(self.periods[(value & 0x0F) as usize])
     }
 
     pub fn update_4(&mut self, value: u8) {
@@ -94,7 +102,7 @@ impl Noise {
     }
 
     pub fn tick(&mut self, time_event: &TimeEvent) {
-        if time_event.contains(TimeEvent::APUClock) {
+        if time_event.contains(TimeEvent::CPUClock) {
             self.feedback.tick();
         }
         if time_event.contains(TimeEvent::QuarterFrame) {
@@ -105,27 +113,63 @@ impl Noise {
         }
     }
 
-    pub fn is_halt(&self) -> bool {
-        self.halt
+    pub fn is_active(&self) -> bool {
+        !self.length.is_mute()
     }
 
-    pub fn is_end(&self) -> bool {
-        self.length.is_end()
+    pub fn set_enable(&mut self, enable: bool) {
+        self.length.set_enable(enable);
     }
 
-    pub fn disable(&mut self) {
-        self.length.disable();
+    // The duty reports the short-loop mode bit.
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            period: self.feedback.period(),
+            volume: self.envelope.value(),
+            duty: self.feedback.mode() as u8,
+            length: self.length.value() as u16,
+            active: self.is_active(),
+        }
     }
 
-    pub fn value(&self) -> Tone {
-        Tone {
-            frequency: 0.0,
-            volume: if self.halt || self.length.is_mute() || self.feedback.is_mute() {
-                0.0
-            } else {
-                (self.envelope.value() as f64) / 15.0
-            },
-            duty: WaveForm::Noise,
+    pub fn output(&self) -> u8 {
+        if self.length.is_mute() || self.feedback.is_mute() {
+            0
+        } else {
+            self.envelope.value()
         }
     }
 }
+
+impl Snapshot for FeedbackRegister {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u16(self.register);
+        state.write_bool(self.mode);
+        state.write_u16(self.period);
+        state.write_u16(self.counter);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u16()?;
+        self.mode = state.read_bool()?;
+        self.period = state.read_u16()?;
+        self.counter = state.read_u16()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Noise {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.halt);
+        self.envelope.save(state);
+        self.feedback.save(state);
+        self.length.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.halt = state.read_bool()?;
+        self.envelope.load(state)?;
+        self.feedback.load(state)?;
+        self.length.load(state)
+    }
+}
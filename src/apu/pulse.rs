@@ -1,7 +1,12 @@
-use libc_print::libc_println;
+use super::{util::*, ChannelState, TimeEvent};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
-use super::{util::*, TimeEvent};
-use super::{CPU_CLOCK, PITCH_RATIO};
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
 
 pub struct Pulse {
     duty: usize,
@@ -30,10 +35,9 @@ impl Pulse {
     pub fn update_1(&mut self, value: u8) {
         self.duty = (value >> 6) as usize;
         self.halt = value & 0b0010_0000 != 0;
-        self.length.set_enable(!self.halt);
+        self.length.set_halt(self.halt);
         self.envelope.set_disable(value & 0b0001_0000 != 0);
         self.envelope.set_volume(value & 0x0F);
-        self.sequence.reset();
     }
 
     pub fn update_2(&mut self, value: u8) {
@@ -50,14 +54,14 @@ impl Pulse {
 
     pub fn update_4(&mut self, value: u8) {
         self.length.set_length(value >> 3);
-        self.sequence.set_timer_high((value & 0b0000_0111));
+        self.sequence.set_timer_high(value & 0b0000_0111);
+        self.sequence.reset();
         self.envelope.reset();
     }
 
     pub fn tick(&mut self, time_event: &TimeEvent) {
         if time_event.contains(TimeEvent::APUClock) {
             self.sequence.tick();
-            self.sweep.change(self.sequence.period());
         }
         if time_event.contains(TimeEvent::QuarterFrame) {
             self.envelope.tick(self.halt);
@@ -65,48 +69,57 @@ impl Pulse {
         if time_event.contains(TimeEvent::HalfFrame) {
             self.length.tick();
             let new_period = self.sweep.tick(self.sequence.period());
-            if new_period != self.sequence.period() {
-                self.sequence.set_timer_high((new_period >> 8) as u8);
-                self.sequence.set_timer_low(new_period as u8);
-            }
+            self.sequence.set_period(new_period);
         }
     }
 
-    pub fn is_halt(&self) -> bool {
-        self.halt
+    pub fn is_active(&self) -> bool {
+        !self.length.is_mute()
     }
 
-    pub fn is_end(&self) -> bool {
-        self.length.is_end()
+    pub fn set_enable(&mut self, enable: bool) {
+        self.length.set_enable(enable);
     }
 
-    pub fn disable(&mut self) {
-        self.length.disable();
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            period: self.sequence.period(),
+            volume: self.envelope.value(),
+            duty: self.duty as u8,
+            length: self.length.value() as u16,
+            active: self.is_active(),
+        }
     }
 
-    pub fn value(&self) -> Tone {
-        let duty = match self.duty {
-            0 => WaveForm::Pulse12,
-            1 => WaveForm::Pulse25,
-            2 => WaveForm::Pulse50,
-            3 => WaveForm::Pusle75,
-            _ => unimplemented!(),
-        };
-        Tone {
-            frequency: CPU_CLOCK
-                / (16.0 * (self.sequence.period() as f64 + 1.0))
-                / PITCH_RATIO as f64,
-            volume: if self.halt
-                || self.sweep.is_mute()
-                || self.length.is_mute()
-                || self.sequence.is_mute()
-            {
-                // libc_println!("{} {}", self.sweep.is_mute(), self.length.is_mute());
-                0.0
-            } else {
-                (self.envelope.value() as f64) / 15.0
-            },
-            duty,
+    // The 4-bit level fed to the DAC.
+    pub fn output(&self) -> u8 {
+        if self.length.is_mute()
+            || self.sweep.is_mute(self.sequence.period())
+            || DUTY_TABLE[self.duty][self.sequence.value() as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.value()
         }
     }
 }
+
+impl Snapshot for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.duty as u8);
+        state.write_bool(self.halt);
+        self.envelope.save(state);
+        self.sweep.save(state);
+        self.sequence.save(state);
+        self.length.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.duty = (state.read_u8()? & 0b11) as usize;
+        self.halt = state.read_bool()?;
+        self.envelope.load(state)?;
+        self.sweep.load(state)?;
+        self.sequence.load(state)?;
+        self.length.load(state)
+    }
+}
@@ -1,12 +1,15 @@
-use libc_print::libc_println;
-
 use super::util::*;
-use super::TimeEvent;
-use super::CPU_CLOCK;
-use super::PITCH_RATIO;
+use super::{ChannelState, TimeEvent};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
 
 pub struct LinearCounter {
-    enable: bool,
+    // The control flag; while set the reload flag is never cleared.
+    control: bool,
     reload: bool,
     reload_value: u8,
     counter: u8,
@@ -15,15 +18,15 @@ pub struct LinearCounter {
 impl LinearCounter {
     pub fn new() -> Self {
         Self {
-            enable: false,
+            control: false,
             reload: false,
             reload_value: 0,
             counter: 0,
         }
     }
 
-    pub fn set_enable(&mut self, flag: bool) {
-        self.enable = flag;
+    pub fn set_control(&mut self, flag: bool) {
+        self.control = flag;
     }
 
     pub fn set_counter(&mut self, counter: u8) {
@@ -31,7 +34,7 @@ impl LinearCounter {
     }
 
     pub fn is_mute(&self) -> bool {
-        !self.enable || self.counter != 0
+        self.counter == 0
     }
 
     pub fn reset(&mut self) {
@@ -40,11 +43,13 @@ impl LinearCounter {
 
     pub fn tick(&mut self) {
         if self.reload {
-            self.reload = false;
             self.counter = self.reload_value;
         } else if self.counter != 0 {
             self.counter -= 1;
         }
+        if !self.control {
+            self.reload = false;
+        }
     }
 }
 
@@ -69,10 +74,9 @@ impl Triangle {
 
     pub fn update_1(&mut self, value: u8) {
         self.halt = value & 0b1000_0000 != 0;
-        self.linear.set_enable(!self.halt);
-        self.length.set_enable(!self.halt);
+        self.linear.set_control(self.halt);
+        self.length.set_halt(self.halt);
         self.linear.set_counter(value & 0b0111_1111);
-        self.sequence.reset();
     }
 
     pub fn update_2(&mut self, _value: u8) {}
@@ -87,11 +91,13 @@ impl Triangle {
         self.linear.reset();
     }
 
+    // The triangle timer runs at the CPU rate, twice as fast as the others.
     pub fn tick(&mut self, time_event: &TimeEvent) {
-        if time_event.contains(TimeEvent::APUClock) {
-            if !self.length.is_mute() && !self.linear.is_mute() {
-                self.sequence.tick();
-            }
+        if time_event.contains(TimeEvent::CPUClock)
+            && !self.length.is_mute()
+            && !self.linear.is_mute()
+        {
+            self.sequence.tick();
         }
         if time_event.contains(TimeEvent::QuarterFrame) {
             self.linear.tick();
@@ -101,29 +107,61 @@ impl Triangle {
         }
     }
 
-    pub fn is_halt(&self) -> bool {
-        self.halt
+    pub fn is_active(&self) -> bool {
+        !self.length.is_mute()
     }
 
-    pub fn is_end(&self) -> bool {
-        self.length.is_end()
+    // The triangle has no volume control; it is either stepping or not.
+    pub fn state(&self) -> ChannelState {
+        let running = !self.length.is_mute() && !self.linear.is_mute();
+        ChannelState {
+            period: self.sequence.period(),
+            volume: if running { 15 } else { 0 },
+            duty: 0,
+            length: self.length.value() as u16,
+            active: self.is_active(),
+        }
     }
 
-    pub fn disable(&mut self) {
-        self.length.disable();
+    pub fn set_enable(&mut self, enable: bool) {
+        self.length.set_enable(enable);
     }
 
-    pub fn value(&self) -> Tone {
-        Tone {
-            frequency: CPU_CLOCK
-                / (32.0 * (self.sequence.period() as f64 + 1.0))
-                / PITCH_RATIO as f64,
-            volume: if self.halt || self.length.is_mute() || self.sequence.is_mute() {
-                0.0
-            } else {
-                1.0
-            },
-            duty: WaveForm::Triangle,
-        }
+    // A silenced triangle holds its current step rather than dropping to 0.
+    pub fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.sequence.value() as usize]
+    }
+}
+
+impl Snapshot for LinearCounter {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.control);
+        state.write_bool(self.reload);
+        state.write_u8(self.reload_value);
+        state.write_u8(self.counter);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.control = state.read_bool()?;
+        self.reload = state.read_bool()?;
+        self.reload_value = state.read_u8()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.halt);
+        self.linear.save(state);
+        self.sequence.save(state);
+        self.length.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.halt = state.read_bool()?;
+        self.linear.load(state)?;
+        self.sequence.load(state)?;
+        self.length.load(state)
     }
 }
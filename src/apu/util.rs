@@ -1,25 +1,10 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub const LENGTH_COUNTER_TABLE: [u8; 0x20] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
     192, 24, 72, 26, 16, 28, 32, 30,
 ];
 
-#[derive(Debug)]
-pub enum WaveForm {
-    Pulse12,
-    Pulse25,
-    Pulse50,
-    Pusle75,
-    Triangle,
-    Noise,
-}
-
-#[derive(Debug)]
-pub struct Tone {
-    pub frequency: f64,
-    pub volume: f64,
-    pub duty: WaveForm,
-}
-
 pub struct Envelope {
     constant: bool,
     volume: u8,
@@ -42,21 +27,22 @@ impl Envelope {
 
     pub fn set_volume(&mut self, period: u8) {
         self.volume = period;
-        self.current_value = period;
     }
 
     pub fn set_disable(&mut self, constant: bool) {
         self.constant = constant
     }
 
-    pub fn tick(&mut self, halt: bool) {
+    // `looping` is the length-counter halt bit, which doubles as the
+    // envelope loop flag.
+    pub fn tick(&mut self, looping: bool) {
         if !self.start_flag {
             if self.current_value != 0 {
                 self.current_value -= 1;
             } else {
                 self.current_value = self.volume;
                 if self.decay_level == 0 {
-                    if !halt {
+                    if looping {
                         self.decay_level = 15;
                     }
                 } else {
@@ -91,9 +77,8 @@ pub struct Sweep {
 
     current_value: u8,
     reload_flag: bool,
-    mute: bool,
+    // Pulse 1 negates with ones' complement, so it subtracts one more.
     ext: u8,
-    change: u16,
 }
 
 impl Sweep {
@@ -106,8 +91,6 @@ impl Sweep {
 
             current_value: 0,
             reload_flag: false,
-            mute: false,
-            change: 0,
             ext,
         }
     }
@@ -117,7 +100,7 @@ impl Sweep {
     }
 
     pub fn set_period(&mut self, period: u8) {
-        self.period = period + 1;
+        self.period = period;
     }
 
     pub fn set_negate(&mut self, negate: bool) {
@@ -128,40 +111,34 @@ impl Sweep {
         self.shift = shift;
     }
 
-    pub fn change(&mut self, timer: u16) {
-        if self.enable {
-            self.change = timer >> self.shift;
-            self.mute = timer < 8 || timer + self.change > 0x07FF;
+    fn target(&self, timer: u16) -> u16 {
+        let change = timer >> self.shift;
+        if self.negate {
+            timer.saturating_sub(change + self.ext as u16)
+        } else {
+            timer + change
         }
     }
 
-    pub fn is_mute(&self) -> bool {
-        self.mute
+    // The channel is silenced whenever the target overflows, even with the
+    // sweep disabled.
+    pub fn is_mute(&self, timer: u16) -> bool {
+        timer < 8 || self.target(timer) > 0x07FF
     }
 
     pub fn tick(&mut self, timer: u16) -> u16 {
-        let next_period = if self.enable && self.current_value != 0 && self.shift != 0 && !self.mute
-        {
-            if timer >= 8 && self.change <= 0x07FF {
-                match self.negate {
-                    true => timer
-                        .checked_sub(self.change + self.ext as u16)
-                        .unwrap_or(0),
-                    false => timer.checked_add(self.change).unwrap_or(u16::MAX),
-                }
+        let next_period =
+            if self.current_value == 0 && self.enable && self.shift != 0 && !self.is_mute(timer) {
+                self.target(timer)
             } else {
                 timer
-            }
-        } else {
-            timer
-        };
+            };
         if self.current_value == 0 || self.reload_flag {
             self.current_value = self.period;
             self.reload_flag = false;
         } else {
             self.current_value -= 1;
         }
-        self.mute = timer < 8 || timer > 0x7FF;
         next_period
     }
 
@@ -171,7 +148,9 @@ impl Sweep {
 }
 
 pub struct LengthCounter {
+    // Set from $4015; a disabled channel ignores length loads.
     enable: bool,
+    halt: bool,
     length: u8,
 }
 
@@ -179,33 +158,38 @@ impl LengthCounter {
     pub fn new() -> Self {
         Self {
             enable: false,
+            halt: false,
             length: 0,
         }
     }
 
     pub fn set_enable(&mut self, flag: bool) {
         self.enable = flag;
+        if !flag {
+            self.length = 0;
+        }
     }
 
-    pub fn set_length(&mut self, length: u8) {
-        self.length = LENGTH_COUNTER_TABLE[length as usize];
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
     }
 
-    pub fn disable(&mut self) {
-        self.enable = false;
-        self.length = 0;
+    pub fn set_length(&mut self, length: u8) {
+        if self.enable {
+            self.length = LENGTH_COUNTER_TABLE[length as usize];
+        }
     }
 
     pub fn is_mute(&self) -> bool {
-        !self.enable || self.length == 0
+        self.length == 0
     }
 
-    pub fn is_end(&self) -> bool {
-        self.length == 0
+    pub fn value(&self) -> u8 {
+        self.length
     }
 
     pub fn tick(&mut self) {
-        if self.length != 0 {
+        if self.length != 0 && !self.halt {
             self.length -= 1;
         }
     }
@@ -234,7 +218,10 @@ impl Sequence {
 
     pub fn set_timer_high(&mut self, value: u8) {
         self.timer = self.timer & 0x00FF | (value as u16) << 8;
-        self.timer_counter = self.timer;
+    }
+
+    pub fn set_period(&mut self, period: u16) {
+        self.timer = period;
     }
 
     pub fn tick(&mut self) {
@@ -257,8 +244,77 @@ impl Sequence {
     pub fn reset(&mut self) {
         self.sequence_counter = 0;
     }
+}
 
-    pub fn is_mute(&self) -> bool {
-        self.timer < 8
+impl Snapshot for Envelope {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.constant);
+        state.write_u8(self.volume);
+        state.write_bool(self.start_flag);
+        state.write_u8(self.decay_level);
+        state.write_u8(self.current_value);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.constant = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.start_flag = state.read_bool()?;
+        self.decay_level = state.read_u8()?;
+        self.current_value = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.enable);
+        state.write_u8(self.period);
+        state.write_bool(self.negate);
+        state.write_u8(self.shift);
+        state.write_u8(self.current_value);
+        state.write_bool(self.reload_flag);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enable = state.read_bool()?;
+        self.period = state.read_u8()?;
+        self.negate = state.read_bool()?;
+        self.shift = state.read_u8()?;
+        self.current_value = state.read_u8()?;
+        self.reload_flag = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.enable);
+        state.write_bool(self.halt);
+        state.write_u8(self.length);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enable = state.read_bool()?;
+        self.halt = state.read_bool()?;
+        self.length = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sequence {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.sequence_counter);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_counter);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.sequence_counter = state.read_u8()?;
+        self.timer = state.read_u16()?;
+        self.timer_counter = state.read_u16()?;
+        if self.sequence_counter >= self.sequence {
+            return Err(StateError::InvalidData);
+        }
+        Ok(())
     }
 }
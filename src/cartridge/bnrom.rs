@@ -0,0 +1,174 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    memory::{MemoryRead, MemoryWrite},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+use super::{Cartridge, Mirroring, PRG_RAM_BANK_SIZE};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+// Mapper 34 covers two unrelated boards: BNROM latches a 32K PRG bank on
+// writes to ROM, while NINA-001 keeps its registers at $7FFD-$7FFF in front
+// of PRG-RAM and adds two 4K CHR banks.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Board {
+    Bnrom,
+    Nina001,
+}
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+    pub writable: bool,
+    board: Board,
+    prg_bank: usize,
+    chr_banks: [usize; 2],
+}
+
+impl Rom {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        prg_ram: Vec<u8>,
+        mirroring: Mirroring,
+        writable: bool,
+        board: Board,
+    ) -> Self {
+        let prg_ram = if prg_ram.is_empty() && board == Board::Nina001 {
+            vec![0u8; PRG_RAM_BANK_SIZE]
+        } else {
+            prg_ram
+        };
+        Self {
+            prg_rom,
+            chr_rom,
+            prg_ram,
+            mirroring,
+            writable,
+            board,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn prg_addr(&self, address: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let offset = address as usize - 0x8000;
+        ((self.prg_bank % bank_count) * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, address: u16) -> usize {
+        let bank_count = self.chr_rom.len() / CHR_BANK_SIZE;
+        let bank = self.chr_banks[address as usize / CHR_BANK_SIZE];
+        (bank % bank_count) * CHR_BANK_SIZE + (address as usize & (CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Cartridge for Rom {
+    fn memory_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0x6000..0x8000 => {
+                if !self.prg_ram.is_empty() {
+                    MemoryRead::Value(self.prg_ram[(address - 0x6000) as usize])
+                } else {
+                    MemoryRead::Value(0)
+                }
+            }
+            0x8000..=0xFFFF => MemoryRead::Value(self.prg_rom[self.prg_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn memory_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match (self.board, address) {
+            (Board::Nina001, 0x7FFD..=0x7FFF) => {
+                match address {
+                    0x7FFD => self.prg_bank = (value & 0x01) as usize,
+                    0x7FFE => self.chr_banks[0] = (value & 0x0F) as usize,
+                    _ => self.chr_banks[1] = (value & 0x0F) as usize,
+                }
+                // The registers sit in front of RAM, which takes the write too.
+                self.prg_ram[(address - 0x6000) as usize] = value;
+                MemoryWrite::Value(value)
+            }
+            (_, 0x6000..0x8000) => {
+                if !self.prg_ram.is_empty() {
+                    self.prg_ram[(address - 0x6000) as usize] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            (Board::Bnrom, 0x8000..=0xFFFF) => {
+                self.prg_bank = value as usize;
+                MemoryWrite::Value(value)
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0..0x2000 => MemoryRead::Value(self.chr_rom[self.chr_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match address {
+            0..0x2000 => {
+                if self.writable {
+                    let addr = self.chr_addr(address);
+                    self.chr_rom[addr] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.mirroring.save(state);
+        state.write_u8(self.prg_bank as u8);
+        state.write_u8(self.chr_banks[0] as u8);
+        state.write_u8(self.chr_banks[1] as u8);
+        state.write_slice(&self.prg_ram);
+        if self.writable {
+            state.write_slice(&self.chr_rom);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(state)?;
+        self.prg_bank = state.read_u8()? as usize;
+        self.chr_banks[0] = state.read_u8()? as usize;
+        self.chr_banks[1] = state.read_u8()? as usize;
+        state.read_slice(&mut self.prg_ram)?;
+        if self.writable {
+            state.read_slice(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
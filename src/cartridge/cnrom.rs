@@ -0,0 +1,134 @@
+use alloc::vec::Vec;
+
+use crate::{
+    memory::{MemoryRead, MemoryWrite},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+use super::{Cartridge, Mirroring, CHR_ROM_BANK_SIZE, PRG_ROM_BANK_SIZE};
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+    pub writable: bool,
+    bank: usize,
+}
+
+impl Rom {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        prg_ram: Vec<u8>,
+        mirroring: Mirroring,
+        writable: bool,
+    ) -> Self {
+        Self {
+            prg_rom,
+            chr_rom,
+            prg_ram,
+            mirroring,
+            writable,
+            bank: 0,
+        }
+    }
+
+    fn prg_addr(&self, address: u16) -> usize {
+        (address as usize - 0x8000) % self.prg_rom.len().min(2 * PRG_ROM_BANK_SIZE)
+    }
+
+    fn chr_addr(&self, address: u16) -> usize {
+        let bank_count = self.chr_rom.len() / CHR_ROM_BANK_SIZE;
+        (self.bank % bank_count) * CHR_ROM_BANK_SIZE + address as usize
+    }
+}
+
+impl Cartridge for Rom {
+    fn memory_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0x6000..0x8000 => {
+                if !self.prg_ram.is_empty() {
+                    MemoryRead::Value(self.prg_ram[(address - 0x6000) as usize])
+                } else {
+                    MemoryRead::Value(0)
+                }
+            }
+            0x8000..=0xFFFF => MemoryRead::Value(self.prg_rom[self.prg_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn memory_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match address {
+            0x6000..0x8000 => {
+                if !self.prg_ram.is_empty() {
+                    self.prg_ram[(address - 0x6000) as usize] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            0x8000..=0xFFFF => {
+                self.bank = value as usize;
+                MemoryWrite::Value(value)
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0..0x2000 => MemoryRead::Value(self.chr_rom[self.chr_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match address {
+            0..0x2000 => {
+                if self.writable {
+                    let addr = self.chr_addr(address);
+                    self.chr_rom[addr] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.mirroring.save(state);
+        state.write_u8(self.bank as u8);
+        state.write_slice(&self.prg_ram);
+        if self.writable {
+            state.write_slice(&self.chr_rom);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(state)?;
+        self.bank = state.read_u8()? as usize;
+        state.read_slice(&mut self.prg_ram)?;
+        if self.writable {
+            state.read_slice(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,15 @@
+use alloc::vec;
 use alloc::vec::Vec;
 
 use crate::{
     memory::{MemoryRead, MemoryWrite},
-    ppu::{Tile, TileSize},
+    state::{Snapshot, StateError, StateReader, StateWriter},
 };
 
-use super::{Cartridge, Mirroring, PRG_ROM_BANK_SIZE};
+use super::{Cartridge, Mirroring, PRG_RAM_BANK_SIZE, PRG_ROM_BANK_SIZE};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
 
 pub struct Rom {
     pub prg_rom: Vec<u8>,
@@ -14,6 +18,12 @@ pub struct Rom {
     pub prg_ram: Vec<u8>,
     pub mirroring: Mirroring,
     pub writable: bool,
+
+    shift: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
 }
 
 impl Rom {
@@ -25,6 +35,13 @@ impl Rom {
         mirroring: Mirroring,
         writable: bool,
     ) -> Self {
+        // MMC1 boards almost always carry 8K of PRG-RAM, even when the header
+        // doesn't declare it.
+        let prg_ram = if prg_ram.len() == 0 {
+            vec![0u8; PRG_RAM_BANK_SIZE]
+        } else {
+            prg_ram
+        };
         Self {
             prg_rom,
             chr_rom,
@@ -32,28 +49,83 @@ impl Rom {
             trainer,
             mirroring,
             writable,
+            shift: 0x10,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..0xA000 => {
+                self.control = value;
+                self.mirroring = match value & 0b0000_0011 {
+                    0 => Mirroring::OneScreenLower,
+                    1 => Mirroring::OneScreenUpper,
+                    2 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            0xA000..0xC000 => self.chr_bank_0 = value,
+            0xC000..0xE000 => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
         }
     }
+
+    fn prg_ram_enable(&self) -> bool {
+        // SNROM reuses CHR bank bit 4 as an extra PRG-RAM disable line.
+        let snrom_disable = self.writable
+            && self.prg_rom.len() <= PRG_OUTER_BANK_SIZE
+            && self.chr_bank_0 & 0b0001_0000 != 0;
+        self.prg_bank & 0b0001_0000 == 0 && !snrom_disable
+    }
+
+    fn prg_addr(&self, address: u16) -> usize {
+        // SUROM/SXROM select the 256K half of a 512K PRG-ROM with CHR bank bit 4.
+        let outer = if self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            (self.chr_bank_0 & 0b0001_0000) as usize
+        } else {
+            0
+        };
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let (low, high) = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & 0x0E, bank | 0x01),
+            2 => (0, bank),
+            _ => (bank, 0x0F),
+        };
+        let bank = if address < 0xC000 { low } else { high };
+        let bank_count = self.prg_rom.len() / PRG_ROM_BANK_SIZE;
+        let offset = (address as usize) & (PRG_ROM_BANK_SIZE - 1);
+        ((outer | bank) % bank_count) * PRG_ROM_BANK_SIZE + offset
+    }
+
+    fn chr_addr(&self, address: u16) -> usize {
+        let bank = if self.control & 0b0001_0000 == 0 {
+            (self.chr_bank_0 & 0x1E) as usize | (address as usize >> 12)
+        } else if address < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+        let bank_count = self.chr_rom.len() / CHR_BANK_SIZE;
+        let offset = (address as usize) & (CHR_BANK_SIZE - 1);
+        (bank % bank_count) * CHR_BANK_SIZE + offset
+    }
 }
 
 impl Cartridge for Rom {
     fn memory_read(&self, address: u16) -> MemoryRead {
         match address {
             0x6000..0x8000 => {
-                if self.prg_ram.len() != 0 {
+                if self.prg_ram_enable() {
                     MemoryRead::Value(self.prg_ram[(address - 0x6000) as usize])
                 } else {
                     MemoryRead::Value(0)
                 }
             }
-            0x8000..0xC000 => MemoryRead::Value(self.prg_rom[(address - 0x8000) as usize]),
-            0xC000..=0xFFFF => {
-                if self.prg_rom.len() <= PRG_ROM_BANK_SIZE {
-                    MemoryRead::Value(self.prg_rom[(address - 0xC000) as usize])
-                } else {
-                    MemoryRead::Value(self.prg_rom[(address - 0x8000) as usize])
-                }
-            }
+            0x8000..=0xFFFF => MemoryRead::Value(self.prg_rom[self.prg_addr(address)]),
             _ => MemoryRead::Pass,
         }
     }
@@ -61,26 +133,34 @@ impl Cartridge for Rom {
     fn memory_write(&mut self, address: u16, value: u8) -> MemoryWrite {
         match address {
             0x6000..0x8000 => {
-                if self.prg_ram.len() != 0 {
+                if self.prg_ram_enable() {
                     self.prg_ram[(address - 0x6000) as usize] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            0x8000..=0xFFFF => {
+                if value & 0b1000_0000 != 0 {
+                    self.shift = 0x10;
+                    self.control |= 0x0C;
                 } else {
-                    self.chr_rom[(address - 0x6000) as usize] = value;
+                    let complete = self.shift & 0x01 != 0;
+                    self.shift = (self.shift >> 1) | ((value & 0x01) << 4);
+                    if complete {
+                        self.write_register(address, self.shift);
+                        self.shift = 0x10;
+                    }
                 }
                 MemoryWrite::Value(value)
             }
             _ => MemoryWrite::Block,
         }
     }
-    fn tile(&self, idx: usize, size: TileSize) -> Tile {
-        match size {
-            TileSize::Tile8 => Tile::Tile8(self.chr_rom[idx..idx + 16].to_vec()),
-            TileSize::Tile16 => todo!(),
-        }
-    }
 
     fn ppu_read(&self, address: u16) -> MemoryRead {
         match address {
-            0..0x2000 => MemoryRead::Value(self.chr_rom[address as usize]),
+            0..0x2000 => MemoryRead::Value(self.chr_rom[self.chr_addr(address)]),
             _ => MemoryRead::Pass,
         }
     }
@@ -89,7 +169,8 @@ impl Cartridge for Rom {
         match address {
             0..0x2000 => {
                 if self.writable {
-                    self.chr_rom[address as usize] = value;
+                    let addr = self.chr_addr(address);
+                    self.chr_rom[addr] = value;
                     MemoryWrite::Value(value)
                 } else {
                     MemoryWrite::Block
@@ -98,4 +179,191 @@ impl Cartridge for Rom {
             _ => MemoryWrite::Block,
         }
     }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.mirroring.save(state);
+        state.write_u8(self.shift);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank_0);
+        state.write_u8(self.chr_bank_1);
+        state.write_u8(self.prg_bank);
+        state.write_slice(&self.prg_ram);
+        if self.writable {
+            state.write_slice(&self.chr_rom);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(state)?;
+        self.shift = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank_0 = state.read_u8()?;
+        self.chr_bank_1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        state.read_slice(&mut self.prg_ram)?;
+        if self.writable {
+            state.read_slice(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // PRG marked with its 16K bank number and CHR with its 4K one.
+    fn board(prg_banks: usize, chr_banks: usize, writable: bool) -> Rom {
+        let mut prg_rom = vec![0u8; prg_banks * PRG_ROM_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        let mut chr_rom = vec![0u8; chr_banks * CHR_BANK_SIZE];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        Rom::new(
+            prg_rom,
+            chr_rom,
+            Vec::new(),
+            Vec::new(),
+            Mirroring::Horizontal,
+            writable,
+        )
+    }
+
+    // Five serial writes, LSB first.
+    fn write_register(rom: &mut Rom, address: u16, value: u8) {
+        for bit in 0..5 {
+            rom.memory_write(address, value >> bit & 0x01);
+        }
+    }
+
+    fn read(rom: &Rom, address: u16) -> u8 {
+        match rom.memory_read(address) {
+            MemoryRead::Value(value) => value,
+            MemoryRead::Pass => panic!("{:04X} not mapped", address),
+        }
+    }
+
+    fn chr(rom: &Rom, address: u16) -> u8 {
+        match rom.ppu_read(address) {
+            MemoryRead::Value(value) => value,
+            MemoryRead::Pass => panic!("{:04X} not mapped", address),
+        }
+    }
+
+    #[test]
+    fn test_shift_reset() {
+        let mut rom = board(8, 2, false);
+        write_register(&mut rom, 0x8000, 0x08);
+        write_register(&mut rom, 0xE000, 0x02);
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (0, 2));
+
+        // Bit 7 drops the partial value and goes back to fixing the last bank.
+        rom.memory_write(0xE000, 0x01);
+        rom.memory_write(0xE000, 0x01);
+        rom.memory_write(0xE000, 0x80);
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (2, 7));
+        write_register(&mut rom, 0xE000, 0x03);
+        assert_eq!(read(&rom, 0x8000), 3);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut rom = board(8, 2, false);
+        write_register(&mut rom, 0xE000, 0x05);
+        // 16K at $8000, last bank fixed at $C000.
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (5, 7));
+
+        write_register(&mut rom, 0x8000, 0x08);
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (0, 5));
+
+        // 32K ignores the low bit.
+        write_register(&mut rom, 0x8000, 0x00);
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (4, 5));
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut rom = board(2, 8, false);
+        write_register(&mut rom, 0xA000, 0x05);
+        write_register(&mut rom, 0xC000, 0x06);
+        // 8K ignores the low bit and CHR bank 1.
+        write_register(&mut rom, 0x8000, 0x0C);
+        assert_eq!((chr(&rom, 0x0000), chr(&rom, 0x1000)), (4, 5));
+
+        write_register(&mut rom, 0x8000, 0x1C);
+        assert_eq!((chr(&rom, 0x0000), chr(&rom, 0x1000)), (5, 6));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut rom = board(2, 2, false);
+        for (control, mirroring) in [
+            (0, Mirroring::OneScreenLower),
+            (1, Mirroring::OneScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            write_register(&mut rom, 0x8000, 0x0C | control);
+            assert_eq!(rom.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut rom = board(8, 2, false);
+        rom.memory_write(0x6000, 0x42);
+        assert_eq!(read(&rom, 0x6000), 0x42);
+
+        write_register(&mut rom, 0xE000, 0x10);
+        assert_eq!(read(&rom, 0x6000), 0);
+        assert!(matches!(rom.memory_write(0x6000, 0x24), MemoryWrite::Block));
+        write_register(&mut rom, 0xE000, 0x00);
+        assert_eq!(read(&rom, 0x6000), 0x42);
+
+        // CHR bit 4 only disables RAM on SNROM, which has CHR-RAM.
+        write_register(&mut rom, 0xA000, 0x10);
+        assert_eq!(read(&rom, 0x6000), 0x42);
+
+        let mut snrom = board(8, 2, true);
+        snrom.memory_write(0x6000, 0x42);
+        write_register(&mut snrom, 0xA000, 0x10);
+        assert_eq!(read(&snrom, 0x6000), 0);
+        assert!(matches!(
+            snrom.memory_write(0x6000, 0x24),
+            MemoryWrite::Block
+        ));
+        write_register(&mut snrom, 0xA000, 0x00);
+        assert_eq!(read(&snrom, 0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_surom_outer_bank() {
+        let mut rom = board(32, 2, true);
+        write_register(&mut rom, 0xE000, 0x02);
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (2, 15));
+
+        // CHR bank 0 bit 4 picks the upper 256K, fixed bank included, and
+        // leaves PRG-RAM alone.
+        rom.memory_write(0x6000, 0x42);
+        write_register(&mut rom, 0xA000, 0x10);
+        assert_eq!((read(&rom, 0x8000), read(&rom, 0xC000)), (18, 31));
+        assert_eq!(read(&rom, 0x6000), 0x42);
+    }
 }
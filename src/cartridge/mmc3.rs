@@ -0,0 +1,425 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    memory::{MemoryRead, MemoryWrite},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+use super::{Cartridge, Mirroring, PRG_RAM_BANK_SIZE};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// A12 has to stay low for about three M2 cycles before a rise clocks the counter.
+const A12_FILTER: u8 = 10;
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+    pub writable: bool,
+
+    bank_select: u8,
+    registers: [u8; 8],
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enable: bool,
+    irq_pending: bool,
+
+    a12: bool,
+    a12_low: u8,
+}
+
+impl Rom {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        prg_ram: Vec<u8>,
+        mirroring: Mirroring,
+        writable: bool,
+    ) -> Self {
+        let prg_ram = if prg_ram.is_empty() {
+            vec![0u8; PRG_RAM_BANK_SIZE]
+        } else {
+            prg_ram
+        };
+        Self {
+            prg_rom,
+            chr_rom,
+            prg_ram,
+            mirroring,
+            writable,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_protect: 0b1000_0000,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enable: false,
+            irq_pending: false,
+            a12: false,
+            a12_low: 0,
+        }
+    }
+
+    fn prg_ram_enable(&self) -> bool {
+        self.prg_ram_protect & 0b1000_0000 != 0
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_enable() && self.prg_ram_protect & 0b0100_0000 == 0
+    }
+
+    fn prg_addr(&self, address: u16) -> usize {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let second_last = bank_count - 2;
+        let swap = self.bank_select & 0b0100_0000 != 0;
+        let bank = match ((address - 0x8000) / PRG_BANK_SIZE as u16, swap) {
+            (0, false) | (2, true) => (self.registers[6] & 0x3F) as usize,
+            (0, true) | (2, false) => second_last,
+            (1, _) => (self.registers[7] & 0x3F) as usize,
+            _ => bank_count - 1,
+        };
+        let offset = (address as usize) & (PRG_BANK_SIZE - 1);
+        (bank % bank_count) * PRG_BANK_SIZE + offset
+    }
+
+    fn chr_addr(&self, address: u16) -> usize {
+        let inversion = if self.bank_select & 0b1000_0000 != 0 {
+            4
+        } else {
+            0
+        };
+        let bank = match (address as usize / CHR_BANK_SIZE) ^ inversion {
+            0 => self.registers[0] & 0xFE,
+            1 => self.registers[0] | 0x01,
+            2 => self.registers[1] & 0xFE,
+            3 => self.registers[1] | 0x01,
+            slot => self.registers[slot - 2],
+        } as usize;
+        let bank_count = self.chr_rom.len() / CHR_BANK_SIZE;
+        let offset = (address as usize) & (CHR_BANK_SIZE - 1);
+        (bank % bank_count) * CHR_BANK_SIZE + offset
+    }
+
+    fn clock_scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enable {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Cartridge for Rom {
+    fn memory_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0x6000..0x8000 => {
+                if self.prg_ram_enable() {
+                    MemoryRead::Value(self.prg_ram[(address - 0x6000) as usize])
+                } else {
+                    MemoryRead::Value(0)
+                }
+            }
+            0x8000..=0xFFFF => MemoryRead::Value(self.prg_rom[self.prg_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn memory_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match (address, address & 0x01 == 0) {
+            (0x6000..0x8000, _) => {
+                if self.prg_ram_writable() {
+                    self.prg_ram[(address - 0x6000) as usize] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            (0x8000..0xA000, true) => {
+                self.bank_select = value;
+                MemoryWrite::Value(value)
+            }
+            (0x8000..0xA000, false) => {
+                self.registers[(self.bank_select & 0b0111) as usize] = value;
+                MemoryWrite::Value(value)
+            }
+            (0xA000..0xC000, true) => {
+                if self.mirroring != Mirroring::FourScreen {
+                    self.mirroring = if value & 0x01 == 0 {
+                        Mirroring::Vertical
+                    } else {
+                        Mirroring::Horizontal
+                    };
+                }
+                MemoryWrite::Value(value)
+            }
+            (0xA000..0xC000, false) => {
+                self.prg_ram_protect = value;
+                MemoryWrite::Value(value)
+            }
+            (0xC000..0xE000, true) => {
+                self.irq_latch = value;
+                MemoryWrite::Value(value)
+            }
+            (0xC000..0xE000, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+                MemoryWrite::Value(value)
+            }
+            (0xE000..=0xFFFF, true) => {
+                self.irq_enable = false;
+                self.irq_pending = false;
+                MemoryWrite::Value(value)
+            }
+            (0xE000..=0xFFFF, false) => {
+                self.irq_enable = true;
+                MemoryWrite::Value(value)
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0..0x2000 => MemoryRead::Value(self.chr_rom[self.chr_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match address {
+            0..0x2000 => {
+                if self.writable {
+                    let addr = self.chr_addr(address);
+                    self.chr_rom[addr] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_bus(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low >= A12_FILTER {
+            self.clock_scanline();
+        }
+        self.a12_low = if a12 {
+            0
+        } else {
+            self.a12_low.saturating_add(1)
+        };
+        self.a12 = a12;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.mirroring.save(state);
+        state.write_u8(self.bank_select);
+        state.write_bytes(&self.registers);
+        state.write_u8(self.prg_ram_protect);
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_bool(self.irq_reload);
+        state.write_bool(self.irq_enable);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.a12);
+        state.write_u8(self.a12_low);
+        state.write_slice(&self.prg_ram);
+        if self.writable {
+            state.write_slice(&self.chr_rom);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(state)?;
+        self.bank_select = state.read_u8()?;
+        state.read_bytes(&mut self.registers)?;
+        self.prg_ram_protect = state.read_u8()?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_reload = state.read_bool()?;
+        self.irq_enable = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.a12 = state.read_bool()?;
+        self.a12_low = state.read_u8()?;
+        state.read_slice(&mut self.prg_ram)?;
+        if self.writable {
+            state.read_slice(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 128K of PRG marked with the 8K bank number, 32K of CHR with the 1K one.
+    fn rom() -> Rom {
+        let mut prg_rom = vec![0u8; 16 * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        let mut chr_rom = vec![0u8; 32 * CHR_BANK_SIZE];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        Rom::new(prg_rom, chr_rom, Vec::new(), Mirroring::Vertical, false)
+    }
+
+    fn read(rom: &Rom, address: u16) -> u8 {
+        match rom.memory_read(address) {
+            MemoryRead::Value(value) => value,
+            MemoryRead::Pass => panic!("{:04X} not mapped", address),
+        }
+    }
+
+    fn chr(rom: &Rom, address: u16) -> u8 {
+        match rom.ppu_read(address) {
+            MemoryRead::Value(value) => value,
+            MemoryRead::Pass => panic!("{:04X} not mapped", address),
+        }
+    }
+
+    // A rise on A12 after it has been low long enough to pass the filter.
+    fn scanline(rom: &mut Rom) {
+        for _ in 0..A12_FILTER {
+            rom.ppu_bus(0x0000);
+        }
+        rom.ppu_bus(0x1000);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut rom = rom();
+        for (register, bank) in [(6, 3), (7, 5)] {
+            rom.memory_write(0x8000, register);
+            rom.memory_write(0x8001, bank);
+        }
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|address| read(&rom, address));
+        assert_eq!(banks, [3, 5, 14, 15]);
+
+        // Bit 6 swaps R6 with the fixed second-last bank.
+        rom.memory_write(0x8000, 0x40);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|address| read(&rom, address));
+        assert_eq!(banks, [14, 5, 3, 15]);
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut rom = rom();
+        for (register, bank) in [(0, 8), (1, 11), (2, 20), (3, 21), (4, 22), (5, 23)] {
+            rom.memory_write(0x8000, register);
+            rom.memory_write(0x8001, bank);
+        }
+        let slots = |rom: &Rom| core::array::from_fn(|slot| chr(rom, slot as u16 * 0x400));
+        // R0/R1 are 2K banks and ignore their low bit.
+        assert_eq!(slots(&rom), [8, 9, 10, 11, 20, 21, 22, 23]);
+
+        // Bit 7 swaps the 2K and 1K halves.
+        rom.memory_write(0x8000, 0x80);
+        assert_eq!(slots(&rom), [20, 21, 22, 23, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut rom = rom();
+        rom.memory_write(0x6000, 0x42);
+        assert_eq!(read(&rom, 0x6000), 0x42);
+
+        // Enabled but write-protected.
+        rom.memory_write(0xA001, 0xC0);
+        assert!(matches!(rom.memory_write(0x6000, 0x24), MemoryWrite::Block));
+        assert_eq!(read(&rom, 0x6000), 0x42);
+
+        // Disabled: reads come back empty and writes are dropped.
+        rom.memory_write(0xA001, 0x00);
+        assert_eq!(read(&rom, 0x6000), 0);
+        assert!(matches!(rom.memory_write(0x6000, 0x24), MemoryWrite::Block));
+
+        rom.memory_write(0xA001, 0x80);
+        assert_eq!(read(&rom, 0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut rom = rom();
+        rom.memory_write(0xC000, 2);
+        rom.memory_write(0xC001, 0);
+        rom.memory_write(0xE001, 0);
+
+        // The first clock reloads the counter; it then counts down to 0.
+        scanline(&mut rom);
+        assert_eq!(rom.irq_counter, 2);
+        scanline(&mut rom);
+        assert!(!rom.irq());
+        scanline(&mut rom);
+        assert_eq!(rom.irq_counter, 0);
+        assert!(rom.irq());
+
+        // $E000 acknowledges and disables.
+        rom.memory_write(0xE000, 0);
+        assert!(!rom.irq());
+        scanline(&mut rom);
+        scanline(&mut rom);
+        scanline(&mut rom);
+        assert_eq!(rom.irq_counter, 0);
+        assert!(!rom.irq());
+
+        // A counter at 0 reloads from the latch on the next clock.
+        rom.memory_write(0xE001, 0);
+        scanline(&mut rom);
+        assert_eq!(rom.irq_counter, 2);
+        assert!(!rom.irq());
+    }
+
+    #[test]
+    fn test_a12_filter() {
+        let mut rom = rom();
+        rom.memory_write(0xC000, 5);
+        rom.memory_write(0xC001, 0);
+        scanline(&mut rom);
+        assert_eq!(rom.irq_counter, 5);
+
+        // Sprite fetches toggle A12 within a few dots; those rises don't count.
+        for _ in 0..8 {
+            for _ in 0..A12_FILTER - 1 {
+                rom.ppu_bus(0x0000);
+            }
+            rom.ppu_bus(0x1000);
+        }
+        assert_eq!(rom.irq_counter, 5);
+
+        scanline(&mut rom);
+        assert_eq!(rom.irq_counter, 4);
+    }
+}
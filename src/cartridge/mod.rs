@@ -1,28 +1,65 @@
+mod axrom;
+mod bnrom;
+mod cnrom;
+mod gxrom;
 mod mmc1;
+mod mmc3;
 mod nrom;
 mod uxrom;
+mod vrc6;
+
+use core::fmt;
 
 use alloc::vec::Vec;
 use alloc::{boxed::Box, vec};
 use libc_print::libc_println;
 
 use crate::{
+    apu::AudioHandler,
     device::IOHandler,
     memory::{MemoryBus, MemoryRead, MemoryWrite},
-    ppu::{PpuHandler, Tile, TileSize},
+    ppu::PpuHandler,
+    state::{Snapshot, StateError, StateReader, StateWriter},
 };
 
 const MAGIC_WORD: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 0x10;
+const TRAINER_SIZE: usize = 0x200;
 const PRG_ROM_BANK_SIZE: usize = 0x4000;
 const CHR_ROM_BANK_SIZE: usize = 0x2000;
 const PRG_RAM_BANK_SIZE: usize = 0x2000;
 
-pub trait Cartridge {
+pub trait Cartridge: Snapshot {
     fn memory_read(&self, address: u16) -> MemoryRead;
     fn memory_write(&mut self, address: u16, value: u8) -> MemoryWrite;
-    fn tile(&self, idx: usize, size: TileSize) -> Tile;
     fn ppu_read(&self, address: u16) -> MemoryRead;
     fn ppu_write(&mut self, address: u16, value: u8) -> MemoryWrite;
+    fn mirroring(&self) -> Mirroring;
+    fn prg_ram(&self) -> &[u8];
+    fn prg_ram_mut(&mut self) -> &mut [u8];
+
+    // Where each of the four nametables is mapped, asked on every access.
+    // Tables mapped to the cartridge go through `ppu_read`/`ppu_write`.
+    fn nametable(&self, table: u8) -> Nametable {
+        self.mirroring().nametable(table)
+    }
+
+    // Every address the PPU drives onto the CHR bus, for mappers that snoop it.
+    fn ppu_bus(&mut self, _address: u16) {}
+
+    // Called once per CPU cycle, from the APU's cycle loop, for anything on
+    // the board that counts M2: IRQ counters and expansion sound alike.
+    fn cpu_clock(&mut self) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Expansion sound, in the units of the APU mixer, where pulse 1 alone at
+    // full volume is ~0.149.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -30,30 +67,157 @@ pub enum Mirroring {
     Vertical,
     Horizontal,
     FourScreen,
+    OneScreenLower,
+    OneScreenUpper,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Nametable {
+    // One of the two 1K pages of the console's own VRAM.
+    Ciram(u8),
+    Cartridge,
+}
+
+impl Mirroring {
+    // Four-screen boards put the two extra tables in RAM of their own.
+    pub fn nametable(self, table: u8) -> Nametable {
+        match (self, table) {
+            (Mirroring::Vertical, _) => Nametable::Ciram(table & 0x01),
+            (Mirroring::Horizontal, _) => Nametable::Ciram(table >> 1),
+            (Mirroring::FourScreen, 0 | 1) => Nametable::Ciram(table),
+            (Mirroring::FourScreen, _) => Nametable::Cartridge,
+            (Mirroring::OneScreenLower, _) => Nametable::Ciram(0),
+            (Mirroring::OneScreenUpper, _) => Nametable::Ciram(1),
+        }
+    }
+}
+
+impl Snapshot for Mirroring {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(match self {
+            Mirroring::Vertical => 0,
+            Mirroring::Horizontal => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::OneScreenLower => 3,
+            Mirroring::OneScreenUpper => 4,
+        });
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.read_u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::OneScreenLower,
+            4 => Mirroring::OneScreenUpper,
+            _ => return Err(StateError::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RomError {
+    BadMagic,
+    TruncatedHeader,
+    CorruptHeader,
+    TrainerMissing,
+    EmptyPrgRom,
+    TruncatedPrgRom { expected: usize, found: usize },
+    TruncatedChrRom { expected: usize, found: usize },
+    UnsupportedMapper(u16),
+    UnsupportedNes2Feature(&'static str),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "not an iNES file"),
+            RomError::TruncatedHeader => write!(f, "file is too short to hold an iNES header"),
+            RomError::CorruptHeader => write!(f, "iNES header contains garbage in reserved bytes"),
+            RomError::TrainerMissing => write!(f, "header announces a trainer but the file ends"),
+            RomError::EmptyPrgRom => write!(f, "header declares no PRG-ROM"),
+            RomError::TruncatedPrgRom { expected, found } => write!(
+                f,
+                "PRG-ROM is truncated ({} of {} bytes present)",
+                found, expected
+            ),
+            RomError::TruncatedChrRom { expected, found } => write!(
+                f,
+                "CHR-ROM is truncated ({} of {} bytes present)",
+                found, expected
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            RomError::UnsupportedNes2Feature(feature) => {
+                write!(f, "NES 2.0 feature not supported: {}", feature)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HeaderFormat {
+    INes,
+    Nes2,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu: u8, hardware: u8 },
+    Playchoice10,
+    Extended(u8),
 }
 
 pub struct RomInfo {
-    pub mapper: u8,
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
     pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
     pub prg_rom_size: usize,
     pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+    pub checksum: u32,
+    // Writes to ROM-area registers are ANDed with the byte the ROM drives.
+    pub bus_conflicts: bool,
 }
 
-pub struct Rom(Box<dyn Cartridge>, RomInfo);
-
-impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Self, ()> {
+impl RomInfo {
+    fn parse(raw: &[u8]) -> Result<Self, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(if raw.len() >= 4 && raw[0..4] != MAGIC_WORD {
+                RomError::BadMagic
+            } else {
+                RomError::TruncatedHeader
+            });
+        }
         if &raw[0..4] != MAGIC_WORD {
-            return Err(());
+            return Err(RomError::BadMagic);
         }
         let ctrl1 = raw[6];
         let ctrl2 = raw[7];
-
-        // mapper
-        let mapper = ctrl1 >> 4 | ctrl2 & 0b1111_0000;
-        if ctrl2 & 0b0000_1100 != 0 {
-            return Err(());
-        }
+        let format = match ctrl2 & 0b0000_1100 {
+            0b0000_0000 => HeaderFormat::INes,
+            0b0000_1000 => HeaderFormat::Nes2,
+            _ => return Err(RomError::CorruptHeader),
+        };
 
         // mirroring
         let four_screen = ctrl1 & 0b0000_1000 != 0;
@@ -64,34 +228,178 @@ impl Rom {
             (false, false) => Mirroring::Horizontal,
         };
 
-        // trainer
-        let trainer_enable = ctrl1 & 0b0000_0100 != 0;
-        let trainer = if trainer_enable {
-            raw[0x10..0x210].to_vec()
-        } else {
-            Vec::new()
+        let mut info = Self {
+            format,
+            mapper: (ctrl1 >> 4 | ctrl2 & 0b1111_0000) as u16,
+            submapper: 0,
+            mirroring,
+            battery: ctrl1 & 0b0000_0010 != 0,
+            trainer: ctrl1 & 0b0000_0100 != 0,
+            prg_rom_size: (raw[4] as usize) * PRG_ROM_BANK_SIZE,
+            chr_rom_size: (raw[5] as usize) * CHR_ROM_BANK_SIZE,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console: ConsoleType::Nes,
+            misc_roms: 0,
+            expansion_device: 0,
+            checksum: crc32(&raw[HEADER_SIZE..]),
+            bus_conflicts: false,
         };
 
-        // prg_ram
-        let prg_ram = if ctrl1 & 0b0000_0010 != 0 {
-            Vec::<u8>::with_capacity((raw[8] as usize) * PRG_RAM_BANK_SIZE)
+        match format {
+            HeaderFormat::INes => {
+                // Byte 8 counts 8K units, with 0 meaning one for compatibility.
+                let prg_ram = raw[8].max(1) as usize * PRG_RAM_BANK_SIZE;
+                if info.battery {
+                    info.prg_nvram_size = prg_ram;
+                } else {
+                    info.prg_ram_size = prg_ram;
+                }
+                if info.chr_rom_size == 0 {
+                    info.chr_ram_size = CHR_ROM_BANK_SIZE;
+                }
+                if raw[9] & 0x01 != 0 {
+                    info.timing = Timing::Pal;
+                }
+                info.console = match ctrl2 & 0b0000_0011 {
+                    1 => ConsoleType::VsSystem {
+                        ppu: 0,
+                        hardware: 0,
+                    },
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Nes,
+                };
+            }
+            HeaderFormat::Nes2 => {
+                info.mapper |= ((raw[8] & 0x0F) as u16) << 8;
+                info.submapper = raw[8] >> 4;
+                info.prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_BANK_SIZE)?;
+                info.chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_BANK_SIZE)?;
+                info.prg_ram_size = nes2_ram_size(raw[10] & 0x0F);
+                info.prg_nvram_size = nes2_ram_size(raw[10] >> 4);
+                info.chr_ram_size = nes2_ram_size(raw[11] & 0x0F);
+                info.chr_nvram_size = nes2_ram_size(raw[11] >> 4);
+                info.timing = match raw[12] & 0b11 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+                info.console = match ctrl2 & 0b0000_0011 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem {
+                        ppu: raw[13] & 0x0F,
+                        hardware: raw[13] >> 4,
+                    },
+                    2 => ConsoleType::Playchoice10,
+                    _ => match raw[13] & 0x0F {
+                        0 => ConsoleType::Nes,
+                        1 => ConsoleType::VsSystem {
+                            ppu: 0,
+                            hardware: 0,
+                        },
+                        2 => ConsoleType::Playchoice10,
+                        console => ConsoleType::Extended(console),
+                    },
+                };
+                info.misc_roms = raw[14] & 0b11;
+                info.expansion_device = raw[15] & 0x3F;
+            }
+        }
+
+        // NES 2.0 submapper 2 marks the discrete boards that have them;
+        // otherwise they're off, which nearly every game tolerates.
+        info.bus_conflicts = matches!(info.mapper, 2 | 3 | 7) && info.submapper == 2;
+
+        if let ConsoleType::Extended(_) = info.console {
+            return Err(RomError::UnsupportedNes2Feature("extended console type"));
+        }
+        if info.prg_rom_size == 0 {
+            return Err(RomError::EmptyPrgRom);
+        }
+        Ok(info)
+    }
+}
+
+// Sizes with an MSB nibble of $F are written as 2^E * (MM * 2 + 1).
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Result<usize, RomError> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .filter(|size| size.leading_zeros() >= 3)
+            .map(|size| size * multiplier)
+            .ok_or(RomError::UnsupportedNes2Feature("ROM size exponent"))
+    } else {
+        Ok(((msb as usize) << 8 | lsb as usize) * unit)
+    }
+}
+
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+pub struct Rom {
+    cartridge: Box<dyn Cartridge>,
+    info: RomInfo,
+    // PRG-RAM writes since the frontend last persisted it.
+    battery_dirty: bool,
+    // The extra nametable RAM of four-screen boards.
+    four_screen_ram: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(raw: &Vec<u8>) -> Result<Self, RomError> {
+        let info = RomInfo::parse(raw)?;
+        let mirroring = info.mirroring;
+
+        // trainer
+        let trainer = if info.trainer {
+            raw.get(HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE)
+                .ok_or(RomError::TrainerMissing)?
+                .to_vec()
         } else {
             Vec::new()
         };
 
+        // prg_ram, padded up to whole 8K banks; smaller chips read back the
+        // padding rather than mirroring
+        let prg_ram_size = info.prg_ram_size + info.prg_nvram_size;
+        let prg_ram = vec![0u8; prg_ram_size.next_multiple_of(PRG_RAM_BANK_SIZE)];
+
         // prg_rom & chr_rom
-        let prg_rom_size = (raw[4] as usize) * PRG_ROM_BANK_SIZE;
-        let chr_rom_size = (raw[5] as usize) * CHR_ROM_BANK_SIZE;
+        let prg_rom_size = info.prg_rom_size;
+        let chr_rom_size = info.chr_rom_size;
 
-        let prg_rom_start = 0x10 + if trainer_enable { 0x200 } else { 0 };
+        let prg_rom_start = HEADER_SIZE + if info.trainer { TRAINER_SIZE } else { 0 };
         let chr_rom_start = prg_rom_start + prg_rom_size;
 
-        let prg_rom = raw[prg_rom_start..prg_rom_start + prg_rom_size].to_vec();
+        let prg_rom = raw
+            .get(prg_rom_start..chr_rom_start)
+            .ok_or(RomError::TruncatedPrgRom {
+                expected: prg_rom_size,
+                found: raw.len().saturating_sub(prg_rom_start),
+            })?
+            .to_vec();
         let chr_ram = chr_rom_size == 0;
         let chr_rom = if chr_ram {
-            vec![0u8; 0x2000]
+            let size = info.chr_ram_size + info.chr_nvram_size;
+            vec![0u8; size.max(CHR_ROM_BANK_SIZE)]
         } else {
-            raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec()
+            raw.get(chr_rom_start..chr_rom_start + chr_rom_size)
+                .ok_or(RomError::TruncatedChrRom {
+                    expected: chr_rom_size,
+                    found: raw.len().saturating_sub(chr_rom_start),
+                })?
+                .to_vec()
         };
 
         // libc_println!("prg_rom size: 0x{:04X}", chr_rom_start - prg_rom_start);
@@ -112,61 +420,209 @@ impl Rom {
         //     raw[prg_rom_start + prg_rom_size - 5]
         // );
 
-        let info = RomInfo {
-            mapper,
-            mirroring,
-            prg_rom_size,
-            chr_rom_size,
-        };
-
-        match mapper {
+        let cartridge: Box<dyn Cartridge> = match info.mapper {
             0 => {
                 use nrom::Rom;
-                Ok(Self(
-                    Box::new(Rom::new(
-                        prg_rom, chr_rom, trainer, prg_ram, mirroring, chr_ram,
-                    )),
-                    info,
+                Box::new(Rom::new(
+                    prg_rom, chr_rom, trainer, prg_ram, mirroring, chr_ram,
+                ))
+            }
+            1 => {
+                use mmc1::Rom;
+                Box::new(Rom::new(
+                    prg_rom, chr_rom, trainer, prg_ram, mirroring, chr_ram,
                 ))
             }
             2 => {
                 use uxrom::Rom;
-                Ok(Self(
-                    Box::new(Rom::new(
-                        prg_rom, chr_rom, trainer, prg_ram, mirroring, chr_ram,
-                    )),
-                    info,
+                Box::new(Rom::new(
+                    prg_rom, chr_rom, trainer, prg_ram, mirroring, chr_ram,
                 ))
             }
-            _ => Err(()),
-        }
+            4 => {
+                use mmc3::Rom;
+                Box::new(Rom::new(prg_rom, chr_rom, prg_ram, mirroring, chr_ram))
+            }
+            3 => {
+                use cnrom::Rom;
+                Box::new(Rom::new(prg_rom, chr_rom, prg_ram, mirroring, chr_ram))
+            }
+            7 => {
+                use axrom::Rom;
+                Box::new(Rom::new(prg_rom, chr_rom, prg_ram, chr_ram))
+            }
+            11 | 66 => {
+                use gxrom::{Board, Rom};
+                let board = if info.mapper == 11 {
+                    Board::ColorDreams
+                } else {
+                    Board::Gxrom
+                };
+                Box::new(Rom::new(
+                    prg_rom, chr_rom, prg_ram, mirroring, chr_ram, board,
+                ))
+            }
+            34 => {
+                use bnrom::{Board, Rom};
+                // Only NINA-001 has CHR-ROM to bank, unless the submapper says.
+                let board = match info.submapper {
+                    1 => Board::Nina001,
+                    2 => Board::Bnrom,
+                    _ if chr_rom_size > CHR_ROM_BANK_SIZE => Board::Nina001,
+                    _ => Board::Bnrom,
+                };
+                Box::new(Rom::new(
+                    prg_rom, chr_rom, prg_ram, mirroring, chr_ram, board,
+                ))
+            }
+            24 | 26 => {
+                use vrc6::Rom;
+                Box::new(Rom::new(
+                    prg_rom,
+                    chr_rom,
+                    prg_ram,
+                    mirroring,
+                    chr_ram,
+                    info.mapper == 26,
+                ))
+            }
+            mapper => return Err(RomError::UnsupportedMapper(mapper)),
+        };
+        let four_screen_ram = if mirroring == Mirroring::FourScreen {
+            vec![0u8; 0x800]
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            cartridge,
+            info,
+            battery_dirty: false,
+            four_screen_ram,
+        })
     }
 
     pub fn info<'a>(&'a self) -> &'a RomInfo {
-        &self.1
+        &self.info
+    }
+
+    pub fn irq(&self) -> bool {
+        self.cartridge.irq()
+    }
+
+    // Only the discrete latch boards can conflict; the rest decode their
+    // registers off the ROM's data lines and ignore this.
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.info.bus_conflicts = enabled && matches!(self.info.mapper, 2 | 3 | 7 | 11 | 34 | 66);
+    }
+
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        if self.info.battery {
+            Some(self.cartridge.prg_ram())
+        } else {
+            None
+        }
+    }
+
+    // Accepts saves of any size; extra bytes are dropped and missing ones
+    // keep their power-on value.
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        if !self.info.battery {
+            return;
+        }
+        let ram = self.cartridge.prg_ram_mut();
+        let len = data.len().min(ram.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        self.battery_dirty = false;
+    }
+
+    pub fn battery_dirty(&mut self) -> bool {
+        let previous = self.battery_dirty;
+        self.battery_dirty = false;
+        previous
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.cartridge.save(state);
+        state.write_slice(&self.four_screen_ram);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cartridge.load(state)?;
+        state.read_slice(&mut self.four_screen_ram)?;
+        self.battery_dirty = self.info.battery;
+        Ok(())
     }
 }
 
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 0x01 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 impl IOHandler for Rom {
     fn read(&mut self, mmu: &MemoryBus, address: u16) -> MemoryRead {
-        self.0.memory_read(address)
+        self.cartridge.memory_read(address)
     }
 
     fn write(&mut self, mmu: &MemoryBus, address: u16, value: u8) -> MemoryWrite {
-        self.0.memory_write(address, value)
+        let value = match (self.info.bus_conflicts, self.cartridge.memory_read(address)) {
+            (true, MemoryRead::Value(rom)) if address >= 0x8000 => value & rom,
+            _ => value,
+        };
+        let result = self.cartridge.memory_write(address, value);
+        if let (0x6000..0x8000, MemoryWrite::Value(_)) = (address, &result) {
+            self.battery_dirty = true;
+        }
+        result
     }
 }
 
-impl PpuHandler for Rom {
-    fn tile(&self, idx: usize, size: TileSize) -> Tile {
-        self.0.tile(idx, size)
+impl AudioHandler for Rom {
+    fn clock(&mut self) -> f32 {
+        self.cartridge.cpu_clock();
+        self.cartridge.audio_output()
     }
+}
 
+// Nametable accesses the mapper doesn't claim fall through to the
+// four-screen RAM, if there is any.
+impl PpuHandler for Rom {
     fn read(&self, address: u16) -> MemoryRead {
-        self.0.ppu_read(address)
+        match (self.cartridge.ppu_read(address), address) {
+            (MemoryRead::Pass, 0x2000..0x3000) if !self.four_screen_ram.is_empty() => {
+                MemoryRead::Value(self.four_screen_ram[address as usize & 0x07FF])
+            }
+            (result, _) => result,
+        }
     }
 
     fn write(&mut self, address: u16, value: u8) -> MemoryWrite {
-        self.0.ppu_write(address, value)
+        match (self.cartridge.ppu_write(address, value), address) {
+            (MemoryWrite::Block, 0x2000..0x3000) if !self.four_screen_ram.is_empty() => {
+                self.four_screen_ram[address as usize & 0x07FF] = value;
+                MemoryWrite::Value(value)
+            }
+            (result, _) => result,
+        }
+    }
+
+    fn nametable(&self, table: u8) -> Nametable {
+        self.cartridge.nametable(table)
+    }
+
+    fn bus(&mut self, address: u16) {
+        self.cartridge.ppu_bus(address)
     }
 }
@@ -3,7 +3,7 @@ use libc_print::libc_println;
 
 use crate::{
     memory::{MemoryRead, MemoryWrite},
-    ppu::{Tile, TileSize},
+    state::{Snapshot, StateError, StateReader, StateWriter},
 };
 
 use super::{Cartridge, Mirroring, PRG_ROM_BANK_SIZE};
@@ -83,13 +83,6 @@ impl Cartridge for Rom {
             _ => MemoryWrite::Block,
         }
     }
-    fn tile(&self, idx: usize, size: TileSize) -> Tile {
-        match size {
-            TileSize::Tile8 => Tile::Tile8(self.chr_rom[idx..idx + 16].to_vec()),
-            TileSize::Tile16 => Tile::Tile16(self.chr_rom[idx..idx + 32].to_vec()),
-        }
-    }
-
     fn ppu_read(&self, address: u16) -> MemoryRead {
         match address {
             0..0x2000 => MemoryRead::Value(self.chr_rom[address as usize]),
@@ -110,4 +103,37 @@ impl Cartridge for Rom {
             _ => MemoryWrite::Block,
         }
     }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.mirroring.save(state);
+        state.write_u32(self.bank as u32);
+        state.write_slice(&self.prg_ram);
+        if self.writable {
+            state.write_slice(&self.chr_rom);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(state)?;
+        self.bank = state.read_u32()? as usize % (self.last_bank + 1);
+        state.read_slice(&mut self.prg_ram)?;
+        if self.writable {
+            state.read_slice(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,520 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{
+    memory::{MemoryRead, MemoryWrite},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+use super::{Cartridge, Mirroring, PRG_RAM_BANK_SIZE};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// One step of the chip's DAC, in APU mixer units; a full-volume VRC6 pulse
+// comes out about as loud as a 2A03 one.
+const LEVEL: f32 = 0.00996;
+
+struct Pulse {
+    mode: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enable: bool,
+
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Self {
+        Self {
+            mode: false,
+            duty: 0,
+            volume: 0,
+            period: 0,
+            enable: false,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.mode = value & 0x80 != 0;
+                self.duty = (value >> 4) & 0x07;
+                self.volume = value & 0x0F;
+            }
+            1 => self.period = self.period & 0x0F00 | value as u16,
+            _ => {
+                self.period = self.period & 0x00FF | ((value & 0x0F) as u16) << 8;
+                self.enable = value & 0x80 != 0;
+                if !self.enable {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, shift: u8) {
+        if !self.enable {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Mode forces the output high whatever the duty.
+    fn output(&self) -> u8 {
+        if self.enable && (self.mode || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enable: bool,
+
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Self {
+        Self {
+            rate: 0,
+            period: 0,
+            enable: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = self.period & 0x0F00 | value as u16,
+            _ => {
+                self.period = self.period & 0x00FF | ((value & 0x0F) as u16) << 8;
+                self.enable = value & 0x80 != 0;
+                if !self.enable {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    // The rate is added on every other step and the seventh add resets it.
+    fn tick(&mut self, shift: u8) {
+        if !self.enable {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 0x01 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+    pub writable: bool,
+    // VRC6b (mapper 26) has A0 and A1 swapped on the register pins.
+    swapped: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr: [u8; 8],
+    banking: u8,
+
+    frequency_control: u8,
+    pulse: [Pulse; 2],
+    sawtooth: Sawtooth,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_control: u8,
+    irq_pending: bool,
+}
+
+impl Rom {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        prg_ram: Vec<u8>,
+        mirroring: Mirroring,
+        writable: bool,
+        swapped: bool,
+    ) -> Self {
+        let prg_ram = if prg_ram.is_empty() {
+            vec![0u8; PRG_RAM_BANK_SIZE]
+        } else {
+            prg_ram
+        };
+        Self {
+            prg_rom,
+            chr_rom,
+            prg_ram,
+            mirroring,
+            writable,
+            swapped,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0, 1, 2, 3, 4, 5, 6, 7],
+            banking: 0,
+            frequency_control: 0,
+            pulse: [Pulse::new(), Pulse::new()],
+            sawtooth: Sawtooth::new(),
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: 341,
+            irq_control: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_ram_enable(&self) -> bool {
+        self.banking & 0b1000_0000 != 0
+    }
+
+    fn prg_addr(&self, address: u16) -> usize {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match address {
+            0x8000..0xC000 => (self.prg_16k as usize & 0x0F) * 2 + (address as usize >> 13 & 0x01),
+            0xC000..0xE000 => self.prg_8k as usize & 0x1F,
+            _ => bank_count - 1,
+        };
+        let offset = (address as usize) & (PRG_BANK_SIZE - 1);
+        (bank % bank_count) * PRG_BANK_SIZE + offset
+    }
+
+    // Mode 0 has eight 1K banks, mode 1 four 2K banks, and modes 2 and 3 four
+    // 1K banks below two 2K ones. A 2K bank takes A10 from the PPU unless
+    // bit 5 is set.
+    fn chr_addr(&self, address: u16) -> usize {
+        let slot = address as usize / CHR_BANK_SIZE;
+        let a10 = (slot & 0x01) as u8;
+        let wide = |register: u8| {
+            if self.banking & 0b0010_0000 != 0 {
+                register
+            } else {
+                register & 0xFE | a10
+            }
+        };
+        let bank = match self.banking & 0b11 {
+            0 => self.chr[slot],
+            1 => wide(self.chr[slot / 2]),
+            _ if slot < 4 => self.chr[slot],
+            _ => wide(self.chr[4 + (slot - 4) / 2]),
+        } as usize;
+        let bank_count = self.chr_rom.len() / CHR_BANK_SIZE;
+        let offset = (address as usize) & (CHR_BANK_SIZE - 1);
+        (bank % bank_count) * CHR_BANK_SIZE + offset
+    }
+
+    // In scanline mode a prescaler counts 341 PPU dots, three per CPU cycle.
+    fn clock_irq(&mut self) {
+        if self.irq_control & 0x02 == 0 {
+            return;
+        }
+        if self.irq_control & 0x04 != 0 {
+            self.clock_counter();
+        } else {
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += 341;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    // Bit 2 wins over bit 1 when both are set.
+    fn frequency_shift(&self) -> u8 {
+        match self.frequency_control {
+            control if control & 0x04 != 0 => 8,
+            control if control & 0x02 != 0 => 4,
+            _ => 0,
+        }
+    }
+}
+
+impl Cartridge for Rom {
+    fn memory_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0x6000..0x8000 => {
+                if self.prg_ram_enable() {
+                    MemoryRead::Value(self.prg_ram[(address - 0x6000) as usize])
+                } else {
+                    MemoryRead::Value(0)
+                }
+            }
+            0x8000..=0xFFFF => MemoryRead::Value(self.prg_rom[self.prg_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn memory_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        let register = if self.swapped {
+            (address & 0x01) << 1 | (address & 0x02) >> 1
+        } else {
+            address & 0x03
+        };
+        match (address & 0xF000, register) {
+            (0x6000 | 0x7000, _) => {
+                if self.prg_ram_enable() {
+                    self.prg_ram[(address - 0x6000) as usize] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            (0x8000, _) => {
+                self.prg_16k = value;
+                MemoryWrite::Value(value)
+            }
+            (0x9000, 3) => {
+                self.frequency_control = value;
+                MemoryWrite::Value(value)
+            }
+            (0x9000, _) => {
+                self.pulse[0].write(register, value);
+                MemoryWrite::Value(value)
+            }
+            (0xA000, 3) => MemoryWrite::Value(value),
+            (0xA000, _) => {
+                self.pulse[1].write(register, value);
+                MemoryWrite::Value(value)
+            }
+            (0xB000, 3) => {
+                self.banking = value;
+                self.mirroring = match (value >> 2) & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::OneScreenLower,
+                    _ => Mirroring::OneScreenUpper,
+                };
+                MemoryWrite::Value(value)
+            }
+            (0xB000, _) => {
+                self.sawtooth.write(register, value);
+                MemoryWrite::Value(value)
+            }
+            (0xC000, _) => {
+                self.prg_8k = value;
+                MemoryWrite::Value(value)
+            }
+            (0xD000, _) => {
+                self.chr[register as usize] = value;
+                MemoryWrite::Value(value)
+            }
+            (0xE000, _) => {
+                self.chr[4 + register as usize] = value;
+                MemoryWrite::Value(value)
+            }
+            (0xF000, 0) => {
+                self.irq_latch = value;
+                MemoryWrite::Value(value)
+            }
+            (0xF000, 1) => {
+                self.irq_control = value & 0x07;
+                self.irq_pending = false;
+                if value & 0x02 != 0 {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = 341;
+                }
+                MemoryWrite::Value(value)
+            }
+            (0xF000, 2) => {
+                // Acknowledging copies the "enable after ack" bit into enable.
+                self.irq_pending = false;
+                self.irq_control = self.irq_control & 0b101 | (self.irq_control & 0x01) << 1;
+                MemoryWrite::Value(value)
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> MemoryRead {
+        match address {
+            0..0x2000 => MemoryRead::Value(self.chr_rom[self.chr_addr(address)]),
+            _ => MemoryRead::Pass,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match address {
+            0..0x2000 => {
+                if self.writable {
+                    let addr = self.chr_addr(address);
+                    self.chr_rom[addr] = value;
+                    MemoryWrite::Value(value)
+                } else {
+                    MemoryWrite::Block
+                }
+            }
+            _ => MemoryWrite::Block,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn cpu_clock(&mut self) {
+        self.clock_irq();
+        if self.frequency_control & 0x01 == 0 {
+            let shift = self.frequency_shift();
+            self.pulse[0].tick(shift);
+            self.pulse[1].tick(shift);
+            self.sawtooth.tick(shift);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        let level = self.pulse[0].output() + self.pulse[1].output() + self.sawtooth.output();
+        level as f32 * LEVEL
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.mode);
+        state.write_u8(self.duty);
+        state.write_u8(self.volume);
+        state.write_u16(self.period);
+        state.write_bool(self.enable);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mode = state.read_bool()?;
+        self.duty = state.read_u8()? & 0x07;
+        self.volume = state.read_u8()? & 0x0F;
+        self.period = state.read_u16()? & 0x0FFF;
+        self.enable = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sawtooth {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.rate);
+        state.write_u16(self.period);
+        state.write_bool(self.enable);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+        state.write_u8(self.accumulator);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rate = state.read_u8()? & 0x3F;
+        self.period = state.read_u16()? & 0x0FFF;
+        self.enable = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        self.accumulator = state.read_u8()?;
+        if self.step >= 14 {
+            return Err(StateError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Rom {
+    fn save(&self, state: &mut StateWriter) {
+        self.mirroring.save(state);
+        state.write_u8(self.prg_16k);
+        state.write_u8(self.prg_8k);
+        state.write_bytes(&self.chr);
+        state.write_u8(self.banking);
+        state.write_u8(self.frequency_control);
+        self.pulse[0].save(state);
+        self.pulse[1].save(state);
+        self.sawtooth.save(state);
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_u16(self.irq_prescaler as u16);
+        state.write_u8(self.irq_control);
+        state.write_bool(self.irq_pending);
+        state.write_slice(&self.prg_ram);
+        if self.writable {
+            state.write_slice(&self.chr_rom);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(state)?;
+        self.prg_16k = state.read_u8()?;
+        self.prg_8k = state.read_u8()?;
+        state.read_bytes(&mut self.chr)?;
+        self.banking = state.read_u8()?;
+        self.frequency_control = state.read_u8()?;
+        self.pulse[0].load(state)?;
+        self.pulse[1].load(state)?;
+        self.sawtooth.load(state)?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_prescaler = state.read_u16()? as i16;
+        if !(1..=341).contains(&self.irq_prescaler) {
+            return Err(StateError::InvalidData);
+        }
+        self.irq_control = state.read_u8()? & 0x07;
+        self.irq_pending = state.read_bool()?;
+        state.read_slice(&mut self.prg_ram)?;
+        if self.writable {
+            state.read_slice(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
@@ -3,7 +3,10 @@ mod opcode;
 use bitflags::bitflags;
 use libc_print::{libc_print, libc_println};
 
-use crate::memory::{Bus, MemoryBus};
+use crate::{
+    memory::{Bus, MemoryBus},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
 pub use opcode::OpCode;
 use opcode::OPCODE_TABLE;
 
@@ -55,6 +58,8 @@ pub struct Cpu2A03 {
     pub(crate) sp: u8,
     pub(crate) pc: u16,
     pub(crate) status: Status,
+    irq_inhibit: bool,
+    pub(crate) halted: bool,
 }
 
 impl Cpu2A03 {
@@ -66,6 +71,8 @@ impl Cpu2A03 {
             sp: 0xFD,
             pc: 0,
             status: Status::DEFAULT,
+            irq_inhibit: true,
+            halted: false,
         }
     }
 
@@ -75,6 +82,8 @@ impl Cpu2A03 {
         self.y = 0;
         self.status = Status::DEFAULT;
         self.sp = 0xFD;
+        self.irq_inhibit = true;
+        self.halted = false;
         self.pc = mmu.read_word(0xFFFC);
         // self.pc = 0xC000;
     }
@@ -88,6 +97,7 @@ impl Cpu2A03 {
     }
 
     pub fn execute(&mut self, mmu: &mut MemoryBus, instruction: OpCode) -> u8 {
+        let int = self.status.contains(Status::INT);
         let (elapsed_cycle, is_branched) = match (instruction.execute)(self, mmu, instruction.mode)
         {
             Ok((elapsed_cycle, is_branched)) => (elapsed_cycle, is_branched),
@@ -101,6 +111,13 @@ impl Cpu2A03 {
         if !is_branched {
             self.pc += instruction.size as u16;
         }
+        // CLI, SEI and PLP change I after the interrupt poll of their last
+        // cycle, so the new value only takes effect one instruction later.
+        self.irq_inhibit = if instruction.delays_irq {
+            int
+        } else {
+            self.status.contains(Status::INT)
+        };
         elapsed_cycle
     }
 
@@ -125,6 +142,23 @@ impl Cpu2A03 {
     }
 
     pub fn nmi(&mut self, mmu: &mut MemoryBus) -> u8 {
+        self.interrupt(mmu, 0xFFFA)
+    }
+
+    pub fn irq(&mut self, mmu: &mut MemoryBus) -> u8 {
+        self.interrupt(mmu, 0xFFFE)
+    }
+
+    // KIL/JAM locks the CPU up until the next reset.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn irq_enable(&self) -> bool {
+        !self.irq_inhibit
+    }
+
+    fn interrupt(&mut self, mmu: &mut MemoryBus, vector: u16) -> u8 {
         self.push16(mmu, self.pc);
         let mut flag = self.status.clone();
         flag.set(Status::BRK, false);
@@ -132,8 +166,34 @@ impl Cpu2A03 {
 
         self.push8(mmu, flag.bits());
         self.status.insert(Status::INT);
-        self.pc = mmu.read_word(0xFFFA);
-        2
+        self.irq_inhibit = true;
+        self.pc = mmu.read_word(vector);
+        7
+    }
+}
+
+impl Snapshot for Cpu2A03 {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.a);
+        state.write_u8(self.x);
+        state.write_u8(self.y);
+        state.write_u8(self.sp);
+        state.write_u16(self.pc);
+        state.write_u8(self.status.bits());
+        state.write_bool(self.irq_inhibit);
+        state.write_bool(self.halted);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.sp = state.read_u8()?;
+        self.pc = state.read_u16()?;
+        self.status = Status::from_bits_truncate(state.read_u8()?);
+        self.irq_inhibit = state.read_bool()?;
+        self.halted = state.read_bool()?;
+        Ok(())
     }
 }
 
@@ -333,4 +393,99 @@ mod test {
         }
         assert_eq!(cpu.pc, 3);
     }
+    #[test]
+    fn test_brk_pushes_return_address_and_b_flag() {
+        let mut mmu = MemoryBus::new();
+        let mut cpu = Cpu2A03::new();
+        cpu.pc = 0x0200;
+        cpu.sp = 0xFD;
+        mmu.write_byte(0x0200, 0x00);
+        mmu.write_byte(0xFFFE, 0x34);
+        mmu.write_byte(0xFFFF, 0x12);
+
+        let opcode = cpu.fetch(&mmu);
+        let instruction = cpu.decode(opcode);
+        assert_eq!(cpu.execute(&mut mmu, instruction), 7);
+        assert_eq!(cpu.pc, 0x1234);
+        assert!(cpu.status.contains(Status::INT));
+        assert!(!cpu.status.contains(Status::BRK));
+        assert_eq!(cpu.pop8(&mut mmu) & 0b0011_0000, 0b0011_0000);
+        assert_eq!(cpu.pop16(&mut mmu), 0x0202);
+    }
+
+    #[test]
+    fn test_irq_masked_by_int_flag() {
+        let mut mmu = MemoryBus::new();
+        let mut cpu = Cpu2A03::new();
+        cpu.pc = 0;
+        for (idx, &value) in [0x58, 0xEA, 0x78, 0xEA].iter().enumerate() {
+            mmu.write_byte(idx as u16, value as u8);
+        }
+        mmu.write_byte(0xFFFE, 0x00);
+        mmu.write_byte(0xFFFF, 0x80);
+        assert!(!cpu.irq_enable());
+
+        // CLI only takes effect after the following instruction.
+        let mut enable = [false; 4];
+        for poll in enable.iter_mut() {
+            let opcode = cpu.fetch(&mmu);
+            let instruction = cpu.decode(opcode);
+            cpu.execute(&mut mmu, instruction);
+            *poll = cpu.irq_enable();
+        }
+        assert_eq!(enable, [false, true, true, false]);
+
+        cpu.status.remove(Status::INT);
+        cpu.irq_inhibit = false;
+        assert_eq!(cpu.irq(&mut mmu), 7);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.pop8(&mut mmu) & 0b0011_0000, 0b0010_0000);
+        assert!(!cpu.irq_enable());
+    }
+    #[test]
+    fn test_unofficial_slo_lax_dcp() {
+        let mut mmu = MemoryBus::new();
+        let mut cpu = Cpu2A03::new();
+        cpu.pc = 0;
+        cpu.a = 0x01;
+        for (idx, &value) in [0x07, 0x10, 0xA7, 0x11, 0xC7, 0x12].iter().enumerate() {
+            mmu.write_byte(idx as u16, value as u8);
+        }
+        mmu.write_byte(0x10, 0x81);
+        mmu.write_byte(0x11, 0x42);
+        mmu.write_byte(0x12, 0x43);
+
+        let mut cycles = 0;
+        for _ in 0..3 {
+            let opcode = cpu.fetch(&mmu);
+            let instruction = cpu.decode(opcode);
+            cycles += cpu.execute(&mut mmu, instruction);
+        }
+        assert_eq!(cycles, 5 + 3 + 5);
+        assert_eq!(mmu.read_byte(0x10), 0x02);
+        assert_eq!(mmu.read_byte(0x12), 0x42);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.x, 0x42);
+        assert!(cpu.status.contains(Status::ZERO));
+        assert!(cpu.status.contains(Status::CARRY));
+    }
+
+    #[test]
+    fn test_unofficial_kil_halts() {
+        let mut mmu = MemoryBus::new();
+        let mut cpu = Cpu2A03::new();
+        cpu.pc = 0;
+        for (idx, &value) in [0x02, 0xE8].iter().enumerate() {
+            mmu.write_byte(idx as u16, value as u8);
+        }
+
+        let opcode = cpu.fetch(&mmu);
+        let instruction = cpu.decode(opcode);
+        cpu.execute(&mut mmu, instruction);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0);
+
+        cpu.reset(&mmu);
+        assert!(!cpu.is_halted());
+    }
 }
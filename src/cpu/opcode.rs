@@ -1,6 +1,9 @@
 use crate::memory::Bus;
 
-use super::{AddressingMode, Cpu2A03, Instruction, MemoryBus, Status};
+use super::{page_cross, AddressingMode, Cpu2A03, Instruction, MemoryBus, Status};
+
+// The value of the internal bus that unstable opcodes (XAA, LXA) OR into A.
+const UNSTABLE_MAGIC: u8 = 0xEE;
 
 type OpcodeFn = fn(&mut Cpu2A03, &mut MemoryBus, AddressingMode) -> Result<(u8, bool), ()>;
 
@@ -10,6 +13,8 @@ pub struct OpCode {
     pub size: u8,
     pub mode: AddressingMode,
     pub execute: OpcodeFn,
+    // Set for instructions that change I after their interrupt poll.
+    pub delays_irq: bool,
 }
 
 impl OpCode {
@@ -24,6 +29,14 @@ impl OpCode {
             size,
             mode,
             execute,
+            delays_irq: false,
+        }
+    }
+
+    const fn delaying_irq(self) -> Self {
+        Self {
+            delays_irq: true,
+            ..self
         }
     }
 
@@ -35,270 +48,270 @@ impl OpCode {
 pub static OPCODE_TABLE: [OpCode; 256] = [
     /* 001 */ OpCode::new("BRK", 1, AddressingMode::NoneAddressing, brk),
     /* 002 */ OpCode::new("ORA", 2, AddressingMode::IndirectX, ora),
-    /* 003 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 004 */ OpCode::new("*SLO", 2, AddressingMode::IndirectX, udf),
+    /* 003 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 004 */ OpCode::new("*SLO", 2, AddressingMode::IndirectX, slo),
     /* 005 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPage, nop),
     /* 006 */ OpCode::new("ORA", 2, AddressingMode::ZeroPage, ora),
     /* 007 */ OpCode::new("ASL", 2, AddressingMode::ZeroPage, asl),
-    /* 008 */ OpCode::new("*SLO", 2, AddressingMode::ZeroPage, udf),
+    /* 008 */ OpCode::new("*SLO", 2, AddressingMode::ZeroPage, slo),
     /* 009 */ OpCode::new("PHP", 1, AddressingMode::NoneAddressing, php),
     /* 010 */ OpCode::new("ORA", 2, AddressingMode::Immediate, ora),
     /* 011 */ OpCode::new("ASL", 1, AddressingMode::NoneAddressing, asl),
-    /* 012 */ OpCode::new("*ANC", 2, AddressingMode::Immediate, udf),
+    /* 012 */ OpCode::new("*ANC", 2, AddressingMode::Immediate, anc),
     /* 013 */ OpCode::new("*NOP", 3, AddressingMode::Absolute, nop),
     /* 014 */ OpCode::new("ORA", 3, AddressingMode::Absolute, ora),
     /* 015 */ OpCode::new("ASL", 3, AddressingMode::Absolute, asl),
-    /* 016 */ OpCode::new("*SLO", 3, AddressingMode::Absolute, udf),
+    /* 016 */ OpCode::new("*SLO", 3, AddressingMode::Absolute, slo),
     /* 017 */ OpCode::new("BPL", 2, AddressingMode::Immediate, bpl),
     /* 018 */ OpCode::new("ORA", 2, AddressingMode::IndirectY, ora),
-    /* 019 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 020 */ OpCode::new("*SLO", 2, AddressingMode::IndirectY, udf),
+    /* 019 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 020 */ OpCode::new("*SLO", 2, AddressingMode::IndirectY, slo),
     /* 021 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPageX, nop),
     /* 022 */ OpCode::new("ORA", 2, AddressingMode::ZeroPageX, ora),
     /* 023 */ OpCode::new("ASL", 2, AddressingMode::ZeroPageX, asl),
-    /* 024 */ OpCode::new("*SLO", 2, AddressingMode::ZeroPageX, udf),
+    /* 024 */ OpCode::new("*SLO", 2, AddressingMode::ZeroPageX, slo),
     /* 025 */ OpCode::new("CLC", 1, AddressingMode::NoneAddressing, clc),
     /* 026 */ OpCode::new("ORA", 3, AddressingMode::AbsoluteY, ora),
     /* 027 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 028 */ OpCode::new("*SLO", 3, AddressingMode::AbsoluteY, udf),
+    /* 028 */ OpCode::new("*SLO", 3, AddressingMode::AbsoluteY, slo),
     /* 029 */ OpCode::new("*NOP", 3, AddressingMode::AbsoluteX, nop),
     /* 030 */ OpCode::new("ORA", 3, AddressingMode::AbsoluteX, ora),
     /* 031 */ OpCode::new("ASL", 3, AddressingMode::AbsoluteX, asl),
-    /* 032 */ OpCode::new("*SLO", 3, AddressingMode::AbsoluteX, udf),
+    /* 032 */ OpCode::new("*SLO", 3, AddressingMode::AbsoluteX, slo),
     /* 033 */ OpCode::new("JSR", 3, AddressingMode::Immediate, jsr),
     /* 034 */ OpCode::new("AND", 2, AddressingMode::IndirectX, and),
-    /* 035 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 036 */ OpCode::new("*RLA", 2, AddressingMode::IndirectX, udf),
+    /* 035 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 036 */ OpCode::new("*RLA", 2, AddressingMode::IndirectX, rla),
     /* 037 */ OpCode::new("BIT", 2, AddressingMode::ZeroPage, bit),
     /* 038 */ OpCode::new("AND", 2, AddressingMode::ZeroPage, and),
     /* 039 */ OpCode::new("ROL", 2, AddressingMode::ZeroPage, rol),
-    /* 040 */ OpCode::new("*RLA", 2, AddressingMode::ZeroPage, udf),
-    /* 041 */ OpCode::new("PLP", 1, AddressingMode::NoneAddressing, plp),
+    /* 040 */ OpCode::new("*RLA", 2, AddressingMode::ZeroPage, rla),
+    /* 041 */ OpCode::new("PLP", 1, AddressingMode::NoneAddressing, plp).delaying_irq(),
     /* 042 */ OpCode::new("AND", 2, AddressingMode::Immediate, and),
     /* 043 */ OpCode::new("ROL", 1, AddressingMode::NoneAddressing, rol),
-    /* 044 */ OpCode::new("*ANC", 2, AddressingMode::Immediate, udf),
+    /* 044 */ OpCode::new("*ANC", 2, AddressingMode::Immediate, anc),
     /* 045 */ OpCode::new("BIT", 3, AddressingMode::Absolute, bit),
     /* 046 */ OpCode::new("AND", 3, AddressingMode::Absolute, and),
     /* 047 */ OpCode::new("ROL", 3, AddressingMode::Absolute, rol),
-    /* 048 */ OpCode::new("*RLA", 3, AddressingMode::Absolute, udf),
+    /* 048 */ OpCode::new("*RLA", 3, AddressingMode::Absolute, rla),
     /* 049 */ OpCode::new("BMI", 2, AddressingMode::Immediate, bmi),
     /* 050 */ OpCode::new("AND", 2, AddressingMode::IndirectY, and),
-    /* 051 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 052 */ OpCode::new("*RLA", 2, AddressingMode::IndirectY, udf),
+    /* 051 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 052 */ OpCode::new("*RLA", 2, AddressingMode::IndirectY, rla),
     /* 053 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPageX, nop),
     /* 054 */ OpCode::new("AND", 2, AddressingMode::ZeroPageX, and),
     /* 055 */ OpCode::new("ROL", 2, AddressingMode::ZeroPageX, rol),
-    /* 056 */ OpCode::new("*RLA", 2, AddressingMode::ZeroPageX, udf),
+    /* 056 */ OpCode::new("*RLA", 2, AddressingMode::ZeroPageX, rla),
     /* 057 */ OpCode::new("SEC", 1, AddressingMode::NoneAddressing, sec),
     /* 058 */ OpCode::new("AND", 3, AddressingMode::AbsoluteY, and),
     /* 059 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 060 */ OpCode::new("*RLA", 3, AddressingMode::AbsoluteY, udf),
+    /* 060 */ OpCode::new("*RLA", 3, AddressingMode::AbsoluteY, rla),
     /* 061 */ OpCode::new("*NOP", 3, AddressingMode::AbsoluteX, nop),
     /* 062 */ OpCode::new("AND", 3, AddressingMode::AbsoluteX, and),
     /* 063 */ OpCode::new("ROL", 3, AddressingMode::AbsoluteX, rol),
-    /* 064 */ OpCode::new("*RLA", 3, AddressingMode::AbsoluteX, udf),
+    /* 064 */ OpCode::new("*RLA", 3, AddressingMode::AbsoluteX, rla),
     /* 065 */ OpCode::new("RTI", 1, AddressingMode::NoneAddressing, rti),
     /* 066 */ OpCode::new("EOR", 2, AddressingMode::IndirectX, eor),
-    /* 067 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 068 */ OpCode::new("*SRE", 2, AddressingMode::IndirectX, udf),
+    /* 067 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 068 */ OpCode::new("*SRE", 2, AddressingMode::IndirectX, sre),
     /* 069 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPage, nop),
     /* 070 */ OpCode::new("EOR", 2, AddressingMode::ZeroPage, eor),
     /* 071 */ OpCode::new("LSR", 2, AddressingMode::ZeroPage, lsr),
-    /* 072 */ OpCode::new("*SRE", 2, AddressingMode::ZeroPage, udf),
+    /* 072 */ OpCode::new("*SRE", 2, AddressingMode::ZeroPage, sre),
     /* 073 */ OpCode::new("PHA", 1, AddressingMode::NoneAddressing, pha),
     /* 074 */ OpCode::new("EOR", 2, AddressingMode::Immediate, eor),
     /* 075 */ OpCode::new("LSR", 1, AddressingMode::NoneAddressing, lsr),
-    /* 076 */ OpCode::new("*ALR", 2, AddressingMode::Immediate, udf),
+    /* 076 */ OpCode::new("*ALR", 2, AddressingMode::Immediate, alr),
     /* 077 */
     OpCode::new("JMP", 3, AddressingMode::Immediate, jmp), //AddressingMode that acts as Immidiate
     /* 078 */ OpCode::new("EOR", 3, AddressingMode::Absolute, eor),
     /* 079 */ OpCode::new("LSR", 3, AddressingMode::Absolute, lsr),
-    /* 080 */ OpCode::new("*SRE", 3, AddressingMode::Absolute, udf),
+    /* 080 */ OpCode::new("*SRE", 3, AddressingMode::Absolute, sre),
     /* 081 */ OpCode::new("BVC", 2, AddressingMode::Immediate, bvc),
     /* 082 */ OpCode::new("EOR", 2, AddressingMode::IndirectY, eor),
-    /* 083 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 084 */ OpCode::new("*SRE", 2, AddressingMode::IndirectY, udf),
+    /* 083 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 084 */ OpCode::new("*SRE", 2, AddressingMode::IndirectY, sre),
     /* 085 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPageX, nop),
     /* 086 */ OpCode::new("EOR", 2, AddressingMode::ZeroPageX, eor),
     /* 087 */ OpCode::new("LSR", 2, AddressingMode::ZeroPageX, lsr),
-    /* 088 */ OpCode::new("*SRE", 2, AddressingMode::ZeroPageX, udf),
-    /* 089 */ OpCode::new("CLI", 1, AddressingMode::NoneAddressing, cli),
+    /* 088 */ OpCode::new("*SRE", 2, AddressingMode::ZeroPageX, sre),
+    /* 089 */ OpCode::new("CLI", 1, AddressingMode::NoneAddressing, cli).delaying_irq(),
     /* 090 */ OpCode::new("EOR", 3, AddressingMode::AbsoluteY, eor),
     /* 091 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 092 */ OpCode::new("*SRE", 3, AddressingMode::AbsoluteY, udf),
+    /* 092 */ OpCode::new("*SRE", 3, AddressingMode::AbsoluteY, sre),
     /* 093 */ OpCode::new("*NOP", 3, AddressingMode::AbsoluteX, nop),
     /* 094 */ OpCode::new("EOR", 3, AddressingMode::AbsoluteX, eor),
     /* 095 */ OpCode::new("LSR", 3, AddressingMode::AbsoluteX, lsr),
-    /* 096 */ OpCode::new("*SRE", 3, AddressingMode::AbsoluteX, udf),
+    /* 096 */ OpCode::new("*SRE", 3, AddressingMode::AbsoluteX, sre),
     /* 097 */ OpCode::new("RTS", 1, AddressingMode::NoneAddressing, rts),
     /* 098 */ OpCode::new("ADC", 2, AddressingMode::IndirectX, adc),
-    /* 099 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 100 */ OpCode::new("*RRA", 2, AddressingMode::IndirectX, udf),
+    /* 099 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 100 */ OpCode::new("*RRA", 2, AddressingMode::IndirectX, rra),
     /* 101 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPage, nop),
     /* 102 */ OpCode::new("ADC", 2, AddressingMode::ZeroPage, adc),
     /* 103 */ OpCode::new("ROR", 2, AddressingMode::ZeroPage, ror),
-    /* 104 */ OpCode::new("*RRA", 2, AddressingMode::ZeroPage, udf),
+    /* 104 */ OpCode::new("*RRA", 2, AddressingMode::ZeroPage, rra),
     /* 105 */ OpCode::new("PLA", 1, AddressingMode::NoneAddressing, pla),
     /* 106 */ OpCode::new("ADC", 2, AddressingMode::Immediate, adc),
     /* 107 */ OpCode::new("ROR", 1, AddressingMode::NoneAddressing, ror),
-    /* 108 */ OpCode::new("*ARR", 2, AddressingMode::Immediate, udf),
+    /* 108 */ OpCode::new("*ARR", 2, AddressingMode::Immediate, arr),
     /* 109 */
     OpCode::new("JMP", 3, AddressingMode::Absolute, jmp), //AddressingMode:Indirect with 6502 bug
     /* 110 */ OpCode::new("ADC", 3, AddressingMode::Absolute, adc),
     /* 111 */ OpCode::new("ROR", 3, AddressingMode::Absolute, ror),
-    /* 112 */ OpCode::new("*RRA", 3, AddressingMode::Absolute, udf),
+    /* 112 */ OpCode::new("*RRA", 3, AddressingMode::Absolute, rra),
     /* 113 */ OpCode::new("BVS", 2, AddressingMode::Immediate, bvs),
     /* 114 */ OpCode::new("ADC", 2, AddressingMode::IndirectY, adc),
-    /* 115 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 116 */ OpCode::new("*RRA", 2, AddressingMode::IndirectY, udf),
+    /* 115 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 116 */ OpCode::new("*RRA", 2, AddressingMode::IndirectY, rra),
     /* 117 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPageX, nop),
     /* 118 */ OpCode::new("ADC", 2, AddressingMode::ZeroPageX, adc),
     /* 119 */ OpCode::new("ROR", 2, AddressingMode::ZeroPageX, ror),
-    /* 120 */ OpCode::new("*RRA", 2, AddressingMode::ZeroPageX, udf),
-    /* 121 */ OpCode::new("SEI", 1, AddressingMode::NoneAddressing, sei),
+    /* 120 */ OpCode::new("*RRA", 2, AddressingMode::ZeroPageX, rra),
+    /* 121 */ OpCode::new("SEI", 1, AddressingMode::NoneAddressing, sei).delaying_irq(),
     /* 122 */ OpCode::new("ADC", 3, AddressingMode::AbsoluteY, adc),
     /* 123 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 124 */ OpCode::new("*RRA", 3, AddressingMode::AbsoluteY, udf),
+    /* 124 */ OpCode::new("*RRA", 3, AddressingMode::AbsoluteY, rra),
     /* 125 */ OpCode::new("*NOP", 3, AddressingMode::AbsoluteX, nop),
     /* 126 */ OpCode::new("ADC", 3, AddressingMode::AbsoluteX, adc),
     /* 127 */ OpCode::new("ROR", 3, AddressingMode::AbsoluteX, ror),
-    /* 128 */ OpCode::new("*RRA", 3, AddressingMode::AbsoluteX, udf),
+    /* 128 */ OpCode::new("*RRA", 3, AddressingMode::AbsoluteX, rra),
     /* 129 */ OpCode::new("*NOP", 2, AddressingMode::Immediate, nop),
     /* 130 */ OpCode::new("STA", 2, AddressingMode::IndirectX, sta),
     /* 131 */ OpCode::new("*NOP", 2, AddressingMode::Immediate, nop),
-    /* 132 */ OpCode::new("*SAX", 2, AddressingMode::IndirectX, udf),
+    /* 132 */ OpCode::new("*SAX", 2, AddressingMode::IndirectX, sax),
     /* 133 */ OpCode::new("STY", 2, AddressingMode::ZeroPage, sty),
     /* 134 */ OpCode::new("STA", 2, AddressingMode::ZeroPage, sta),
     /* 135 */ OpCode::new("STX", 2, AddressingMode::ZeroPage, stx),
-    /* 136 */ OpCode::new("*SAX", 2, AddressingMode::ZeroPage, udf),
+    /* 136 */ OpCode::new("*SAX", 2, AddressingMode::ZeroPage, sax),
     /* 137 */ OpCode::new("DEY", 1, AddressingMode::NoneAddressing, dey),
     /* 138 */ OpCode::new("*NOP", 2, AddressingMode::Immediate, nop),
     /* 139 */ OpCode::new("TXA", 1, AddressingMode::NoneAddressing, txa),
     /* 140 */
-    OpCode::new("*XAA", 2, AddressingMode::Immediate, udf), //todo: highly unstable and not used
+    OpCode::new("*XAA", 2, AddressingMode::Immediate, xaa),
     /* 141 */ OpCode::new("STY", 3, AddressingMode::Absolute, sty),
     /* 142 */ OpCode::new("STA", 3, AddressingMode::Absolute, sta),
     /* 143 */ OpCode::new("STX", 3, AddressingMode::Absolute, stx),
-    /* 144 */ OpCode::new("*SAX", 3, AddressingMode::Absolute, udf),
+    /* 144 */ OpCode::new("*SAX", 3, AddressingMode::Absolute, sax),
     /* 145 */ OpCode::new("BCC", 2, AddressingMode::Immediate, bcc),
     /* 146 */ OpCode::new("STA", 2, AddressingMode::IndirectY, sta),
-    /* 147 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
+    /* 147 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
     /* 148 */
-    OpCode::new("*AHX", 2, AddressingMode::IndirectY, udf), //todo: highly unstable and not used
+    OpCode::new("*AHX", 2, AddressingMode::IndirectY, ahx),
     /* 149 */ OpCode::new("STY", 2, AddressingMode::ZeroPageX, sty),
     /* 150 */ OpCode::new("STA", 2, AddressingMode::ZeroPageX, sta),
     /* 151 */ OpCode::new("STX", 2, AddressingMode::ZeroPageY, stx),
-    /* 152 */ OpCode::new("*SAX", 2, AddressingMode::ZeroPageY, udf),
+    /* 152 */ OpCode::new("*SAX", 2, AddressingMode::ZeroPageY, sax),
     /* 153 */ OpCode::new("TYA", 1, AddressingMode::NoneAddressing, tya),
     /* 154 */ OpCode::new("STA", 3, AddressingMode::AbsoluteY, sta),
     /* 155 */ OpCode::new("TXS", 1, AddressingMode::NoneAddressing, txs),
     /* 156 */
-    OpCode::new("*TAS", 3, AddressingMode::AbsoluteY, udf), //todo: highly unstable and not used
+    OpCode::new("*TAS", 3, AddressingMode::AbsoluteY, tas),
     /* 157 */
-    OpCode::new("*SHY", 3, AddressingMode::AbsoluteX, udf), //todo: highly unstable and not used
+    OpCode::new("*SHY", 3, AddressingMode::AbsoluteX, shy),
     /* 158 */ OpCode::new("STA", 3, AddressingMode::AbsoluteX, sta),
     /* 159 */
-    OpCode::new("*SHX", 3, AddressingMode::AbsoluteY, udf), //todo: highly unstable and not used
+    OpCode::new("*SHX", 3, AddressingMode::AbsoluteY, shx),
     /* 160 */
-    OpCode::new("*AHX", 3, AddressingMode::AbsoluteY, udf), //todo: highly unstable and not used
+    OpCode::new("*AHX", 3, AddressingMode::AbsoluteY, ahx),
     /* 161 */ OpCode::new("LDY", 2, AddressingMode::Immediate, ldy),
     /* 162 */ OpCode::new("LDA", 2, AddressingMode::IndirectX, lda),
     /* 163 */ OpCode::new("LDX", 2, AddressingMode::Immediate, ldx),
-    /* 164 */ OpCode::new("*LAX", 2, AddressingMode::IndirectX, udf),
+    /* 164 */ OpCode::new("*LAX", 2, AddressingMode::IndirectX, lax),
     /* 165 */ OpCode::new("LDY", 2, AddressingMode::ZeroPage, ldy),
     /* 166 */ OpCode::new("LDA", 2, AddressingMode::ZeroPage, lda),
     /* 167 */ OpCode::new("LDX", 2, AddressingMode::ZeroPage, ldx),
-    /* 168 */ OpCode::new("*LAX", 2, AddressingMode::ZeroPage, udf),
+    /* 168 */ OpCode::new("*LAX", 2, AddressingMode::ZeroPage, lax),
     /* 169 */ OpCode::new("TAY", 1, AddressingMode::NoneAddressing, tay),
     /* 170 */ OpCode::new("LDA", 2, AddressingMode::Immediate, lda),
     /* 171 */ OpCode::new("TAX", 1, AddressingMode::NoneAddressing, tax),
     /* 172 */
-    OpCode::new("*LXA", 2, AddressingMode::Immediate, udf), //todo: highly unstable and not used
+    OpCode::new("*LXA", 2, AddressingMode::Immediate, lxa),
     /* 173 */ OpCode::new("LDY", 3, AddressingMode::Absolute, ldy),
     /* 174 */ OpCode::new("LDA", 3, AddressingMode::Absolute, lda),
     /* 175 */ OpCode::new("LDX", 3, AddressingMode::Absolute, ldx),
-    /* 176 */ OpCode::new("*LAX", 3, AddressingMode::Absolute, udf),
+    /* 176 */ OpCode::new("*LAX", 3, AddressingMode::Absolute, lax),
     /* 177 */ OpCode::new("BCS", 2, AddressingMode::Immediate, bcs),
     /* 178 */ OpCode::new("LDA", 2, AddressingMode::IndirectY, lda),
-    /* 179 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 180 */ OpCode::new("*LAX", 2, AddressingMode::IndirectY, udf),
+    /* 179 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 180 */ OpCode::new("*LAX", 2, AddressingMode::IndirectY, lax),
     /* 181 */ OpCode::new("LDY", 2, AddressingMode::ZeroPageX, ldy),
     /* 182 */ OpCode::new("LDA", 2, AddressingMode::ZeroPageX, lda),
     /* 183 */ OpCode::new("LDX", 2, AddressingMode::ZeroPageY, ldx),
-    /* 184 */ OpCode::new("*LAX", 2, AddressingMode::ZeroPageY, udf),
+    /* 184 */ OpCode::new("*LAX", 2, AddressingMode::ZeroPageY, lax),
     /* 185 */ OpCode::new("CLV", 1, AddressingMode::NoneAddressing, clv),
     /* 186 */ OpCode::new("LDA", 3, AddressingMode::AbsoluteY, lda),
     /* 187 */ OpCode::new("TSX", 1, AddressingMode::NoneAddressing, tsx),
     /* 188 */
-    OpCode::new("*LAS", 3, AddressingMode::AbsoluteY, udf), //todo: highly unstable and not used
+    OpCode::new("*LAS", 3, AddressingMode::AbsoluteY, las),
     /* 189 */ OpCode::new("LDY", 3, AddressingMode::AbsoluteX, ldy),
     /* 190 */ OpCode::new("LDA", 3, AddressingMode::AbsoluteX, lda),
     /* 191 */ OpCode::new("LDX", 3, AddressingMode::AbsoluteY, ldx),
-    /* 192 */ OpCode::new("*LAX", 3, AddressingMode::AbsoluteY, udf),
+    /* 192 */ OpCode::new("*LAX", 3, AddressingMode::AbsoluteY, lax),
     /* 193 */ OpCode::new("CPY", 2, AddressingMode::Immediate, cpy),
     /* 194 */ OpCode::new("CMP", 2, AddressingMode::IndirectX, cmp),
     /* 195 */ OpCode::new("*NOP", 2, AddressingMode::Immediate, nop),
-    /* 196 */ OpCode::new("*DCP", 2, AddressingMode::IndirectX, udf),
+    /* 196 */ OpCode::new("*DCP", 2, AddressingMode::IndirectX, dcp),
     /* 197 */ OpCode::new("CPY", 2, AddressingMode::ZeroPage, cpy),
     /* 198 */ OpCode::new("CMP", 2, AddressingMode::ZeroPage, cmp),
     /* 199 */ OpCode::new("DEC", 2, AddressingMode::ZeroPage, dec),
-    /* 200 */ OpCode::new("*DCP", 2, AddressingMode::ZeroPage, udf),
+    /* 200 */ OpCode::new("*DCP", 2, AddressingMode::ZeroPage, dcp),
     /* 201 */ OpCode::new("INY", 1, AddressingMode::NoneAddressing, iny),
     /* 202 */ OpCode::new("CMP", 2, AddressingMode::Immediate, cmp),
     /* 203 */ OpCode::new("DEX", 1, AddressingMode::NoneAddressing, dex),
-    /* 204 */ OpCode::new("*AXS", 2, AddressingMode::Immediate, udf),
+    /* 204 */ OpCode::new("*AXS", 2, AddressingMode::Immediate, axs),
     /* 205 */ OpCode::new("CPY", 3, AddressingMode::Absolute, cpy),
     /* 206 */ OpCode::new("CMP", 3, AddressingMode::Absolute, cmp),
     /* 207 */ OpCode::new("DEC", 3, AddressingMode::Absolute, dec),
-    /* 208 */ OpCode::new("*DCP", 3, AddressingMode::Absolute, udf),
+    /* 208 */ OpCode::new("*DCP", 3, AddressingMode::Absolute, dcp),
     /* 209 */ OpCode::new("BNE", 2, AddressingMode::Immediate, bne),
     /* 210 */ OpCode::new("CMP", 2, AddressingMode::IndirectY, cmp),
-    /* 211 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 212 */ OpCode::new("*DCP", 2, AddressingMode::IndirectY, udf),
+    /* 211 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 212 */ OpCode::new("*DCP", 2, AddressingMode::IndirectY, dcp),
     /* 213 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPageX, nop),
     /* 214 */ OpCode::new("CMP", 2, AddressingMode::ZeroPageX, cmp),
     /* 215 */ OpCode::new("DEC", 2, AddressingMode::ZeroPageX, dec),
-    /* 216 */ OpCode::new("*DCP", 2, AddressingMode::ZeroPageX, udf),
+    /* 216 */ OpCode::new("*DCP", 2, AddressingMode::ZeroPageX, dcp),
     /* 217 */ OpCode::new("CLD", 1, AddressingMode::NoneAddressing, cld),
     /* 218 */ OpCode::new("CMP", 3, AddressingMode::AbsoluteY, cmp),
     /* 219 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 220 */ OpCode::new("*DCP", 3, AddressingMode::AbsoluteY, udf),
+    /* 220 */ OpCode::new("*DCP", 3, AddressingMode::AbsoluteY, dcp),
     /* 221 */ OpCode::new("*NOP", 3, AddressingMode::AbsoluteX, nop),
     /* 222 */ OpCode::new("CMP", 3, AddressingMode::AbsoluteX, cmp),
     /* 223 */ OpCode::new("DEC", 3, AddressingMode::AbsoluteX, dec),
-    /* 224 */ OpCode::new("*DCP", 3, AddressingMode::AbsoluteX, udf),
+    /* 224 */ OpCode::new("*DCP", 3, AddressingMode::AbsoluteX, dcp),
     /* 225 */ OpCode::new("CPX", 2, AddressingMode::Immediate, cpx),
     /* 226 */ OpCode::new("SBC", 2, AddressingMode::IndirectX, sbc),
     /* 227 */ OpCode::new("*NOP", 2, AddressingMode::Immediate, nop),
-    /* 228 */ OpCode::new("*ISB", 2, AddressingMode::IndirectX, udf),
+    /* 228 */ OpCode::new("*ISB", 2, AddressingMode::IndirectX, isb),
     /* 229 */ OpCode::new("CPX", 2, AddressingMode::ZeroPage, cpx),
     /* 230 */ OpCode::new("SBC", 2, AddressingMode::ZeroPage, sbc),
     /* 231 */ OpCode::new("INC", 2, AddressingMode::ZeroPage, inc),
-    /* 232 */ OpCode::new("*ISB", 2, AddressingMode::ZeroPage, udf),
+    /* 232 */ OpCode::new("*ISB", 2, AddressingMode::ZeroPage, isb),
     /* 233 */ OpCode::new("INX", 1, AddressingMode::NoneAddressing, inx),
     /* 234 */ OpCode::new("SBC", 2, AddressingMode::Immediate, sbc),
     /* 235 */ OpCode::new("NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 236 */ OpCode::new("*SBC", 2, AddressingMode::Immediate, udf),
+    /* 236 */ OpCode::new("*SBC", 2, AddressingMode::Immediate, sbc),
     /* 237 */ OpCode::new("CPX", 3, AddressingMode::Absolute, cpx),
     /* 238 */ OpCode::new("SBC", 3, AddressingMode::Absolute, sbc),
     /* 239 */ OpCode::new("INC", 3, AddressingMode::Absolute, inc),
-    /* 240 */ OpCode::new("*ISB", 3, AddressingMode::Absolute, udf),
+    /* 240 */ OpCode::new("*ISB", 3, AddressingMode::Absolute, isb),
     /* 241 */ OpCode::new("BEQ", 2, AddressingMode::Immediate, beq),
     /* 242 */ OpCode::new("SBC", 2, AddressingMode::IndirectY, sbc),
-    /* 243 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 244 */ OpCode::new("*ISB", 2, AddressingMode::IndirectY, udf),
+    /* 243 */ OpCode::new("*KIL", 1, AddressingMode::NoneAddressing, kil),
+    /* 244 */ OpCode::new("*ISB", 2, AddressingMode::IndirectY, isb),
     /* 245 */ OpCode::new("*NOP", 2, AddressingMode::ZeroPageX, nop),
     /* 246 */ OpCode::new("SBC", 2, AddressingMode::ZeroPageX, sbc),
     /* 247 */ OpCode::new("INC", 2, AddressingMode::ZeroPageX, inc),
-    /* 248 */ OpCode::new("*ISB", 2, AddressingMode::ZeroPageX, udf),
+    /* 248 */ OpCode::new("*ISB", 2, AddressingMode::ZeroPageX, isb),
     /* 249 */ OpCode::new("SED", 1, AddressingMode::NoneAddressing, sed),
     /* 250 */ OpCode::new("SBC", 3, AddressingMode::AbsoluteY, sbc),
     /* 251 */ OpCode::new("*NOP", 1, AddressingMode::NoneAddressing, nop),
-    /* 252 */ OpCode::new("*ISB", 3, AddressingMode::AbsoluteY, udf),
+    /* 252 */ OpCode::new("*ISB", 3, AddressingMode::AbsoluteY, isb),
     /* 253 */ OpCode::new("*NOP", 3, AddressingMode::AbsoluteX, nop),
     /* 254 */ OpCode::new("SBC", 3, AddressingMode::AbsoluteX, sbc),
     /* 255 */ OpCode::new("INC", 3, AddressingMode::AbsoluteX, inc),
-    /* 256 */ OpCode::new("*ISB", 3, AddressingMode::AbsoluteX, udf),
+    /* 256 */ OpCode::new("*ISB", 3, AddressingMode::AbsoluteX, isb),
 ];
 
 pub fn adc(
@@ -521,9 +534,9 @@ pub fn brk(
     mmu: &mut MemoryBus,
     operend: AddressingMode,
 ) -> Result<(u8, bool), ()> {
-    cpu.status.set(Status::BRK, true);
-    cpu.push8(mmu, cpu.status.bits());
-    cpu.push16(mmu, cpu.pc);
+    cpu.push16(mmu, cpu.pc.wrapping_add(2));
+    cpu.push8(mmu, (cpu.status | Status::BRK | Status::BRK2).bits());
+    cpu.status.insert(Status::INT);
     cpu.pc = mmu.read_word(0xFFFE);
     Ok((7, true))
 }
@@ -915,7 +928,7 @@ pub fn nop(
     mmu: &mut MemoryBus,
     operend: AddressingMode,
 ) -> Result<(u8, bool), ()> {
-    let page_crossed = if let operend = AddressingMode::NoneAddressing {
+    let page_crossed = if let AddressingMode::NoneAddressing = operend {
         false
     } else {
         operend.fetch_addr(cpu, mmu).1
@@ -1247,10 +1260,330 @@ pub fn tya(
     Ok((2, false))
 }
 
-pub fn udf(
+pub fn ahx(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    store_high_and(mmu, ptr, cpu.y, cpu.a & cpu.x);
+    Ok(match operend {
+        AddressingMode::AbsoluteY => (5, false),
+        AddressingMode::IndirectY => (6, false),
+        _ => panic!(),
+    })
+}
+
+pub fn alr(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = cpu.a & mmu.read_byte(ptr);
+    let res = rhs >> 1;
+    cpu.status.set(Status::CARRY, rhs & 0b0000_0001 != 0);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, false);
+    cpu.a = res;
+    Ok((2, false))
+}
+
+pub fn anc(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let res = cpu.a & mmu.read_byte(ptr);
+    cpu.status.set(Status::CARRY, res & 0b1000_0000 != 0);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    Ok((2, false))
+}
+
+pub fn arr(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = cpu.a & mmu.read_byte(ptr);
+    let res = rhs >> 1 | (cpu.status.bits() & 0x01) << 7;
+    cpu.status.set(Status::CARRY, res & 0b0100_0000 != 0);
+    cpu.status
+        .set(Status::OVF, ((res >> 6) ^ (res >> 5)) & 0x01 != 0);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    Ok((2, false))
+}
+
+pub fn axs(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr);
+    let lhs = cpu.a & cpu.x;
+    let res = lhs.wrapping_sub(rhs);
+    cpu.status.set(Status::CARRY, lhs >= rhs);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.x = res;
+    Ok((2, false))
+}
+
+pub fn dcp(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr).wrapping_sub(1);
+    mmu.write_byte(ptr, rhs);
+    let res = cpu.a.wrapping_sub(rhs);
+    cpu.status.set(Status::CARRY, cpu.a >= rhs);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    Ok((read_modify_write_cycles(operend), false))
+}
+
+pub fn isb(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr).wrapping_add(1);
+    mmu.write_byte(ptr, rhs);
+    let carry = cpu.status.contains(Status::CARRY);
+    let res = cpu.a.carrying_add(!rhs, carry);
+    cpu.status.set(Status::CARRY, res.1);
+    cpu.status.set(Status::ZERO, res.0 == 0);
+    cpu.status
+        .set(Status::OVF, (!rhs ^ res.0) & (cpu.a ^ res.0) & 0x80 != 0);
+    cpu.status.set(Status::NEG, res.0 & 0b1000_0000 != 0);
+    cpu.a = res.0;
+    Ok((read_modify_write_cycles(operend), false))
+}
+
+pub fn kil(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    cpu.halted = true;
+    Ok((2, true))
+}
+
+pub fn las(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, page_crossed) = operend.fetch_addr(cpu, mmu);
+    let res = mmu.read_byte(ptr) & cpu.sp;
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    cpu.x = res;
+    cpu.sp = res;
+    Ok((4 + page_crossed as u8, false))
+}
+
+pub fn lax(
     cpu: &mut Cpu2A03,
     mmu: &mut MemoryBus,
     operend: AddressingMode,
 ) -> Result<(u8, bool), ()> {
-    Err(())
+    let (ptr, page_crossed) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr);
+    cpu.status.set(Status::ZERO, rhs == 0);
+    cpu.status.set(Status::NEG, rhs & 0b1000_0000 != 0);
+    cpu.a = rhs;
+    cpu.x = rhs;
+    Ok(match operend {
+        AddressingMode::ZeroPage => (3, false),
+        AddressingMode::ZeroPageY => (4, false),
+        AddressingMode::Absolute => (4, false),
+        AddressingMode::AbsoluteY => (4 + page_crossed as u8, false),
+        AddressingMode::IndirectX => (6, false),
+        AddressingMode::IndirectY => (5 + page_crossed as u8, false),
+        _ => panic!(),
+    })
+}
+
+pub fn lxa(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let res = (cpu.a | UNSTABLE_MAGIC) & mmu.read_byte(ptr);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    cpu.x = res;
+    Ok((2, false))
+}
+
+pub fn rla(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr);
+    let shifted = rhs << 1 | (cpu.status.bits() & 0x01);
+    mmu.write_byte(ptr, shifted);
+    let res = cpu.a & shifted;
+    cpu.status.set(Status::CARRY, rhs & 0b1000_0000 != 0);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    Ok((read_modify_write_cycles(operend), false))
+}
+
+pub fn rra(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let value = mmu.read_byte(ptr);
+    let rhs = value >> 1 | (cpu.status.bits() & 0x01) << 7;
+    mmu.write_byte(ptr, rhs);
+    let carry = value & 0b0000_0001 != 0;
+    let res = cpu.a.carrying_add(rhs, carry);
+    cpu.status.set(Status::CARRY, res.1);
+    cpu.status.set(Status::ZERO, res.0 == 0);
+    cpu.status
+        .set(Status::OVF, (rhs ^ res.0) & (cpu.a ^ res.0) & 0x80 != 0);
+    cpu.status.set(Status::NEG, res.0 & 0b1000_0000 != 0);
+    cpu.a = res.0;
+    Ok((read_modify_write_cycles(operend), false))
+}
+
+pub fn sax(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    mmu.write_byte(ptr, cpu.a & cpu.x);
+    Ok(match operend {
+        AddressingMode::ZeroPage => (3, false),
+        AddressingMode::ZeroPageY => (4, false),
+        AddressingMode::Absolute => (4, false),
+        AddressingMode::IndirectX => (6, false),
+        _ => panic!(),
+    })
+}
+
+pub fn shx(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    store_high_and(mmu, ptr, cpu.y, cpu.x);
+    Ok((5, false))
+}
+
+pub fn shy(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    store_high_and(mmu, ptr, cpu.x, cpu.y);
+    Ok((5, false))
+}
+
+pub fn slo(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr);
+    let shifted = rhs << 1;
+    mmu.write_byte(ptr, shifted);
+    let res = cpu.a | shifted;
+    cpu.status.set(Status::CARRY, rhs & 0b1000_0000 != 0);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    Ok((read_modify_write_cycles(operend), false))
+}
+
+pub fn sre(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let rhs = mmu.read_byte(ptr);
+    let shifted = rhs >> 1;
+    mmu.write_byte(ptr, shifted);
+    let res = cpu.a ^ shifted;
+    cpu.status.set(Status::CARRY, rhs & 0b0000_0001 != 0);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    Ok((read_modify_write_cycles(operend), false))
+}
+
+pub fn tas(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    cpu.sp = cpu.a & cpu.x;
+    store_high_and(mmu, ptr, cpu.y, cpu.sp);
+    Ok((5, false))
+}
+
+pub fn xaa(
+    cpu: &mut Cpu2A03,
+    mmu: &mut MemoryBus,
+    operend: AddressingMode,
+) -> Result<(u8, bool), ()> {
+    let (ptr, _) = operend.fetch_addr(cpu, mmu);
+    let res = (cpu.a | UNSTABLE_MAGIC) & cpu.x & mmu.read_byte(ptr);
+    cpu.status.set(Status::ZERO, res == 0);
+    cpu.status.set(Status::NEG, res & 0b1000_0000 != 0);
+    cpu.a = res;
+    Ok((2, false))
+}
+
+fn read_modify_write_cycles(operend: AddressingMode) -> u8 {
+    match operend {
+        AddressingMode::ZeroPage => 5,
+        AddressingMode::ZeroPageX => 6,
+        AddressingMode::Absolute => 6,
+        AddressingMode::AbsoluteX => 7,
+        AddressingMode::AbsoluteY => 7,
+        AddressingMode::IndirectX => 8,
+        AddressingMode::IndirectY => 8,
+        _ => panic!(),
+    }
+}
+
+// SHA/SHX/SHY/TAS AND the stored value with the high byte of the base address
+// plus one, and when indexing crosses a page that value also replaces the
+// high byte of the target address.
+fn store_high_and(mmu: &mut MemoryBus, ptr: u16, index: u8, value: u8) {
+    let base = ptr.wrapping_sub(index as u16);
+    let value = value & ((base >> 8) as u8).wrapping_add(1);
+    let ptr = if page_cross(base, ptr) {
+        (value as u16) << 8 | (ptr & 0x00FF)
+    } else {
+        ptr
+    };
+    mmu.write_byte(ptr, value);
 }
@@ -3,8 +3,10 @@ use core::cell::{Ref, RefCell, RefMut};
 use alloc::rc::Rc;
 
 use crate::{
+    apu::AudioHandler,
+    cartridge::Nametable,
     memory::{MemoryBus, MemoryHandler, MemoryRead, MemoryWrite},
-    ppu::{PpuHandler, Tile, TileSize},
+    ppu::PpuHandler,
 };
 
 pub struct Device<T>(Rc<RefCell<T>>, bool);
@@ -72,23 +74,39 @@ impl<T: IOHandler> MemoryHandler for DevHandler<T> {
 }
 
 impl<T: PpuHandler> PpuHandler for DevHandler<T> {
-    fn tile(&self, idx: usize, size: TileSize) -> Tile {
+    fn read(&self, address: u16) -> MemoryRead {
         match self.0.try_borrow() {
-            Ok(inner) => inner.tile(idx, size),
+            Ok(inner) => inner.read(address),
             Err(_) => panic!(),
         }
     }
 
-    fn read(&self, address: u16) -> MemoryRead {
+    fn write(&mut self, address: u16, value: u8) -> MemoryWrite {
+        match self.0.try_borrow_mut() {
+            Ok(mut inner) => inner.write(address, value),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn nametable(&self, table: u8) -> Nametable {
         match self.0.try_borrow() {
-            Ok(inner) => inner.read(address),
+            Ok(inner) => inner.nametable(table),
             Err(_) => panic!(),
         }
     }
 
-    fn write(&mut self, address: u16, value: u8) -> MemoryWrite {
+    fn bus(&mut self, address: u16) {
         match self.0.try_borrow_mut() {
-            Ok(mut inner) => inner.write(address, value),
+            Ok(mut inner) => inner.bus(address),
+            Err(_) => panic!(),
+        }
+    }
+}
+
+impl<T: AudioHandler> AudioHandler for DevHandler<T> {
+    fn clock(&mut self) -> f32 {
+        match self.0.try_borrow_mut() {
+            Ok(mut inner) => inner.clock(),
             Err(_) => panic!(),
         }
     }
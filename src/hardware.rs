@@ -2,14 +2,15 @@ use core::cell::RefCell;
 
 use alloc::rc::Rc;
 
-use crate::{joypad::JoypadButton, ppu::frame::Frame, Tone};
+use crate::{joypad::JoypadButton, ppu::frame::Frame};
 
 pub trait Hardware {
     fn is_active(&mut self) -> bool;
     fn draw_framebuffer(&mut self, frame_buffer: &Frame);
     fn pad_p1(&mut self) -> JoypadButton;
     fn pad_p2(&mut self) -> JoypadButton;
-    fn play_sound(&mut self, sound: [Tone; 4]);
+    // Called once per frame with the samples produced during it.
+    fn play_sound(&mut self, samples: &[f32]);
 }
 
 pub struct HardwareHandle(Rc<RefCell<dyn Hardware>>);
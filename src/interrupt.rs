@@ -0,0 +1,54 @@
+use core::cell::Cell;
+
+use alloc::rc::Rc;
+use bitflags::bitflags;
+
+bitflags! {
+    #[derive(Clone, Copy)]
+    pub struct IrqSource: u8 {
+        const FrameCounter = 0b0000_0001;
+        const Dmc          = 0b0000_0010;
+        const Mapper       = 0b0000_0100;
+    }
+}
+
+// The /IRQ pin is wired-OR: it stays low while any source holds it.
+#[derive(Clone)]
+pub struct IrqLine(Rc<Cell<IrqSource>>);
+
+impl IrqLine {
+    pub fn new() -> Self {
+        Self(Rc::new(Cell::new(IrqSource::empty())))
+    }
+
+    pub fn assert(&self, source: IrqSource) {
+        self.set(source, true);
+    }
+
+    pub fn acknowledge(&self, source: IrqSource) {
+        self.set(source, false);
+    }
+
+    pub fn set(&self, source: IrqSource, flag: bool) {
+        let mut sources = self.0.get();
+        sources.set(source, flag);
+        self.0.set(sources);
+    }
+
+    #[cfg(test)]
+    pub fn is_asserted(&self, source: IrqSource) -> bool {
+        self.0.get().intersects(source)
+    }
+
+    pub fn sources(&self) -> IrqSource {
+        self.0.get()
+    }
+
+    pub fn restore(&self, sources: IrqSource) {
+        self.0.set(sources);
+    }
+
+    pub fn active(&self) -> bool {
+        !self.0.get().is_empty()
+    }
+}
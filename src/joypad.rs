@@ -4,10 +4,11 @@ use libc_print::libc_println;
 use crate::{
     device::IOHandler,
     memory::{MemoryBus, MemoryRead, MemoryWrite},
+    state::{Snapshot, StateError, StateReader, StateWriter},
 };
 
 bitflags! {
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, Default)]
     pub struct JoypadButton: u8 {
         const Right             = 0b10000000;
         const Left              = 0b01000000;
@@ -20,6 +21,18 @@ bitflags! {
     }
 }
 
+#[derive(Clone, Copy, Default)]
+pub struct Input {
+    pub p1: JoypadButton,
+    pub p2: JoypadButton,
+}
+
+impl Input {
+    pub const fn new(p1: JoypadButton, p2: JoypadButton) -> Self {
+        Self { p1, p2 }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Joypad {
     pub status: [JoypadButton; 2],
@@ -62,6 +75,23 @@ impl Joypad {
     }
 }
 
+impl Snapshot for Joypad {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.status[0].bits());
+        state.write_u8(self.status[1].bits());
+        state.write_bytes(&self.index);
+        state.write_u8(self.ctrl);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.status[0] = JoypadButton::from_bits_truncate(state.read_u8()?);
+        self.status[1] = JoypadButton::from_bits_truncate(state.read_u8()?);
+        state.read_bytes(&mut self.index)?;
+        self.ctrl = state.read_u8()?;
+        Ok(())
+    }
+}
+
 impl IOHandler for Joypad {
     fn read(&mut self, _mmu: &MemoryBus, address: u16) -> MemoryRead {
         match address {
@@ -5,21 +5,29 @@
 
 extern crate alloc;
 
-use alloc::vec::Vec;
+use core::cell::Ref;
+
+use alloc::{boxed::Box, vec::Vec};
 use apu::Apu;
-pub use cartridge::Rom;
+pub use apu::{Channel, ChannelState};
+pub use cartridge::{ConsoleType, HeaderFormat, Rom, RomError, RomInfo, Timing};
 use cpu::{Cpu2A03, Instruction};
 use device::Device;
 use hardware::HardwareHandle;
+use interrupt::{IrqLine, IrqSource};
 use joypad::Joypad;
 use libc_print::{libc_print, libc_println};
 use memory::MemoryBus;
 use ppu::Ppu;
+use state::{Snapshot, StateReader, StateWriter};
 
-pub use apu::{Tone, WaveForm};
 pub use hardware::Hardware;
-pub use joypad::JoypadButton;
+pub use joypad::{Input, JoypadButton};
 pub use ppu::frame::{Frame, HEIGHT, WIDTH};
+pub use ppu::ntsc::{NtscFilter, NtscSettings, NTSC_WIDTH};
+pub use ppu::palette::{Palette, PaletteError};
+pub use region::Region;
+pub use state::StateError;
 
 use crate::memory::Bus;
 
@@ -28,9 +36,13 @@ mod cartridge;
 mod cpu;
 mod device;
 mod hardware;
+mod interrupt;
 mod joypad;
+mod math;
 mod memory;
 mod ppu;
+mod region;
+mod state;
 
 pub struct Nes {
     cpu: Cpu2A03,
@@ -39,22 +51,54 @@ pub struct Nes {
     ppu: Device<Ppu>,
     apu: Device<Apu>,
     pad: Device<Joypad>,
+    irq: IrqLine,
     cycles: usize,
+    region: Region,
+
+    frame: Box<Frame>,
+    frame_ready: bool,
+    audio: Vec<f32>,
 
-    hardware: HardwareHandle,
+    hardware: Option<HardwareHandle>,
+}
+
+pub struct FrameResult<'a> {
+    pub frame: &'a Frame,
+    // Mixed mono samples produced during the frame, 0.0 being silence.
+    pub audio: &'a [f32],
+    pub cycles: usize,
 }
 
 impl Nes {
-    pub fn new<T>(raw: &Vec<u8>, hardware: T) -> Self
+    pub fn new<T>(raw: &Vec<u8>, hardware: T) -> Result<Self, RomError>
     where
         T: Hardware + 'static,
     {
+        let mut nes = Self::headless(raw)?;
+        nes.hardware = Some(HardwareHandle::new(hardware));
+        Ok(nes)
+    }
+
+    // For frontends that drive the machine through `run_frame`/`run_cycles`.
+    // The region comes from the header.
+    pub fn headless(raw: &Vec<u8>) -> Result<Self, RomError> {
+        let rom = Rom::new(raw)?;
+        let region = Region::from_timing(rom.info().timing);
+        Ok(Self::build(rom, region))
+    }
+
+    pub fn with_region(raw: &Vec<u8>, region: Region) -> Result<Self, RomError> {
+        Ok(Self::build(Rom::new(raw)?, region))
+    }
+
+    fn build(rom: Rom, region: Region) -> Self {
         let mut cpu = Cpu2A03::new();
         let mut mmu = MemoryBus::new();
 
-        let rom = Device::new(Rom::new(raw).unwrap());
-        let ppu = Device::new(Ppu::new(rom.handler(), rom.borrow().info().mirroring));
-        let apu = Device::new(Apu::new());
+        let rom = Device::new(rom);
+        let ppu = Device::new(Ppu::new(rom.handler(), region));
+        let irq = IrqLine::new();
+        let apu = Device::new(Apu::new(irq.clone(), region, Box::new(rom.handler())));
 
         let pad = Device::new(Joypad::new());
 
@@ -78,18 +122,92 @@ impl Nes {
             ppu,
             apu,
             pad,
+            irq,
             cycles: 0,
-            hardware: HardwareHandle::new(hardware),
+            region,
+            frame: Box::new(Frame::new()),
+            frame_ready: false,
+            audio: Vec::new(),
+            hardware: None,
         }
     }
 
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
     pub fn step(&mut self) -> bool {
-        let elapsed_cycles = if self.ppu.borrow_mut().nmi() {
+        self.tick();
+        let Some(hardware) = &self.hardware else {
+            return true;
+        };
+        let mut hardware = hardware.get().borrow_mut();
+        if self.frame_ready {
+            self.frame_ready = false;
+            hardware.draw_framebuffer(&self.frame);
+            self.audio.clear();
+            self.apu.borrow_mut().drain_samples(&mut self.audio);
+            hardware.play_sound(&self.audio);
+        }
+
+        let new_p1_state = hardware.pad_p1();
+        let new_p2_state = hardware.pad_p2();
+        if (new_p1_state.bits() != self.pad.borrow().status[0].bits())
+            || (new_p2_state.bits() != self.pad.borrow().status[1].bits())
+        {
+            self.pad.borrow_mut().update(new_p1_state, new_p2_state);
+        }
+        hardware.is_active()
+    }
+
+    pub fn set_input(&mut self, input: Input) {
+        self.pad.borrow_mut().update(input.p1, input.p2);
+    }
+
+    // Runs whole instructions until at least `cycles` CPU cycles have elapsed
+    // and returns how many actually did.
+    pub fn run_cycles(&mut self, cycles: usize) -> usize {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            elapsed += self.tick();
+        }
+        elapsed
+    }
+
+    pub fn run_frame(&mut self, input: Input) -> FrameResult<'_> {
+        self.set_input(input);
+        self.frame_ready = false;
+        let mut cycles = 0;
+        while !self.frame_ready {
+            cycles += self.tick();
+        }
+        self.frame_ready = false;
+        self.audio.clear();
+        self.apu.borrow_mut().drain_samples(&mut self.audio);
+        FrameResult {
+            frame: &self.frame,
+            audio: &self.audio,
+            cycles,
+        }
+    }
+
+    // Executes one instruction (or interrupt entry) and catches the rest of
+    // the machine up to it.
+    fn tick(&mut self) -> usize {
+        let mut interrupt_sequence = true;
+        let elapsed_cycles = if self.cpu.is_halted() {
+            interrupt_sequence = false;
+            1
+        } else if self.ppu.borrow_mut().nmi() {
             // libc_println!("NMI Occured");
+            interrupt_sequence = false;
             self.cpu.nmi(&mut self.mmu)
+        } else if self.irq.active() && self.cpu.irq_enable() {
+            self.cpu.irq(&mut self.mmu)
         } else {
             let opcode = self.cpu.fetch(&self.mmu);
             let instruction = self.cpu.decode(opcode);
+            interrupt_sequence = opcode == 0x00;
             // self.log(&instruction.decode(opcode, &self.cpu));
             self.cpu.execute(&mut self.mmu, instruction)
         };
@@ -102,26 +220,135 @@ impl Nes {
 
         self.cycles += elapsed_cycles as usize;
 
-        let before_nmi = self.ppu.borrow().read_nmi();
-        self.ppu.borrow_mut().step(elapsed_cycles as u16);
-        let after_nmi = self.ppu.borrow().read_nmi();
+        let mut vblank = self.ppu.borrow_mut().step(elapsed_cycles as u16);
 
-        let volume = self.apu.borrow_mut().step(elapsed_cycles as u16);
-        if !before_nmi && after_nmi {
-            let mut ppu = self.ppu.borrow_mut();
-            let frame = ppu.render();
-            self.hardware.get().borrow_mut().draw_framebuffer(frame);
+        // An NMI raised while BRK or IRQ is pushing state hijacks the vector
+        // fetch; the pushed B flag is left as it was.
+        if interrupt_sequence && self.ppu.borrow_mut().nmi() {
+            self.cpu.pc = self.mmu.read_word(0xFFFA);
         }
-        self.hardware.get().borrow_mut().play_sound(volume);
 
-        let new_p1_state = self.hardware.get().borrow_mut().pad_p1();
-        let new_p2_state = self.hardware.get().borrow_mut().pad_p2();
-        if (new_p1_state.bits() != self.pad.borrow().status[0].bits())
-            || (new_p2_state.bits() != self.pad.borrow().status[1].bits())
-        {
-            self.pad.borrow_mut().update(new_p1_state, new_p2_state);
+        // The APU clocks the cartridge along with its own channels.
+        self.apu.borrow_mut().step(&self.mmu, elapsed_cycles as u16);
+        self.irq.set(IrqSource::Mapper, self.rom.borrow().irq());
+        // DMC sample fetches halt the CPU while the rest of the machine runs.
+        let mut elapsed_cycles = elapsed_cycles as usize;
+        loop {
+            let stall = self.apu.borrow_mut().take_stall();
+            if stall == 0 {
+                break;
+            }
+            self.cycles += stall;
+            elapsed_cycles += stall;
+            vblank |= self.ppu.borrow_mut().step(stall as u16);
+            self.apu.borrow_mut().step(&self.mmu, stall as u16);
+            self.irq.set(IrqSource::Mapper, self.rom.borrow().irq());
+        }
+
+        if vblank {
+            (*self.frame).clone_from(self.ppu.borrow().frame());
+            self.frame_ready = true;
         }
-        self.hardware.get().borrow_mut().is_active()
+        elapsed_cycles
+    }
+
+    // Samples are generated at 44.1 kHz unless told otherwise.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.borrow_mut().set_sample_rate(sample_rate)
+    }
+
+    // For frontends using `run_cycles`: moves the samples generated so far
+    // onto the end of `out`.
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        self.apu.borrow_mut().drain_samples(out)
+    }
+
+    // Gain applied to a channel before the mixer; 1.0 is the console's level.
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.apu.borrow_mut().set_volume(channel, volume)
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.apu.borrow_mut().set_muted(channel, muted)
+    }
+
+    // While any channel is soloed, the others are silent.
+    pub fn set_channel_solo(&mut self, channel: Channel, solo: bool) {
+        self.apu.borrow_mut().set_solo(channel, solo)
+    }
+
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        self.apu.borrow().channel_state(channel)
+    }
+
+    // Drawing every sprite on a line removes flicker in games that multiplex
+    // sprites, at the cost of showing what the hardware would drop.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.borrow_mut().set_sprite_limit(enabled)
+    }
+
+    // Discrete boards whose header doesn't say can be forced either way;
+    // mappers with real registers never conflict.
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.rom.borrow_mut().set_bus_conflicts(enabled)
+    }
+
+    pub fn battery_ram(&self) -> Option<Ref<'_, [u8]>> {
+        Ref::filter_map(self.rom.borrow(), |rom| rom.battery_ram()).ok()
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        self.rom.borrow_mut().load_battery_ram(data)
+    }
+
+    // True once after the game has written to battery-backed RAM, so the
+    // frontend knows when the .sav file needs rewriting.
+    pub fn battery_dirty(&mut self) -> bool {
+        self.rom.borrow_mut().battery_dirty()
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.rom.borrow().info().checksum);
+        state.write_u8(self.region.id());
+        self.cpu.save(&mut state);
+        self.mmu.save(&mut state);
+        self.ppu.borrow().save(&mut state);
+        self.apu.borrow().save(&mut state);
+        self.pad.borrow().save(&mut state);
+        self.rom.borrow().save(&mut state);
+        state.write_u8(self.irq.sources().bits());
+        state.write_u64(self.cycles as u64);
+        state.finish()
+    }
+
+    // Nothing is applied unless the whole state parses; a bad state leaves
+    // the running machine untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = self.restore_state(data);
+        if result.is_err() {
+            // Our own snapshot failing to decode is a bug worth reporting
+            // over the original error.
+            self.restore_state(&backup)?;
+        }
+        result
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data, self.rom.borrow().info().checksum)?;
+        if state.read_u8()? != self.region.id() {
+            return Err(StateError::InvalidData);
+        }
+        self.cpu.load(&mut state)?;
+        self.mmu.load(&mut state)?;
+        self.ppu.borrow_mut().load(&mut state)?;
+        self.apu.borrow_mut().load(&mut state)?;
+        self.pad.borrow_mut().load(&mut state)?;
+        self.rom.borrow_mut().load(&mut state)?;
+        let sources = IrqSource::from_bits(state.read_u8()?).ok_or(StateError::InvalidData)?;
+        self.irq.restore(sources);
+        self.cycles = state.read_u64()? as usize;
+        state.finish()
     }
 
     fn log(&mut self, instruction: &Instruction) {
@@ -240,3 +467,461 @@ impl Nes {
         libc_println!("]");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cartridge::Nametable, ppu::PpuHandler};
+    use alloc::vec;
+
+    // NROM image whose reset handler spins on `JMP $8000`.
+    fn spin_rom() -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        raw.resize(0x10, 0);
+        let mut prg = vec![0xEAu8; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        raw.extend(vec![0u8; 0x2000]);
+        raw
+    }
+
+    #[test]
+    fn test_run_frame_headless() {
+        let mut nes = Nes::headless(&spin_rom()).unwrap();
+        nes.run_frame(Input::default());
+        let result = nes.run_frame(Input::default());
+        // 341 * 262 dots / 3, give or take one JMP.
+        assert!((29778..29784).contains(&result.cycles));
+        let expected = result.cycles as f64 * 44100.0 / 1789773.0;
+        assert!((result.audio.len() as f64 - expected).abs() <= 1.0);
+
+        nes.set_sample_rate(48000);
+        let result = nes.run_frame(Input::default());
+        let expected = result.cycles as f64 * 48000.0 / 1789773.0;
+        assert!((result.audio.len() as f64 - expected).abs() <= 1.0);
+        // Nothing is playing, and idle channels don't make a sound.
+        assert!(result.audio.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_rom_errors() {
+        let raw = spin_rom();
+        assert_eq!(
+            Rom::new(&raw[..8].to_vec()).err(),
+            Some(RomError::TruncatedHeader)
+        );
+        assert_eq!(
+            Rom::new(&raw[..0x3000].to_vec()).err(),
+            Some(RomError::TruncatedPrgRom {
+                expected: 0x4000,
+                found: 0x2FF0
+            })
+        );
+
+        let mut bad = raw.clone();
+        bad[0] = b'M';
+        assert_eq!(Rom::new(&bad).err(), Some(RomError::BadMagic));
+
+        let mut bad = raw.clone();
+        bad[6] |= 0b0000_0100;
+        assert_eq!(
+            Rom::new(&bad).err(),
+            Some(RomError::TruncatedChrRom {
+                expected: 0x2000,
+                found: 0x1E00
+            })
+        );
+        assert_eq!(
+            Rom::new(&bad[..0x100].to_vec()).err(),
+            Some(RomError::TrainerMissing)
+        );
+
+        let mut bad = raw.clone();
+        bad[6] = 0xF0;
+        bad[7] = 0xF0;
+        assert_eq!(
+            Rom::new(&bad).err(),
+            Some(RomError::UnsupportedMapper(0xFF))
+        );
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut raw = spin_rom();
+        raw[7] = 0b0000_1000 | 0x10;
+        raw[8] = 0x31;
+        raw[10] = 0x07;
+        raw[11] = 0x70;
+        raw[12] = 0x01;
+        raw[15] = 0x02;
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::UnsupportedMapper(0x110))
+        );
+
+        raw[7] = 0b0000_1000;
+        raw[8] = 0x30;
+        let rom = Rom::new(&raw).ok().unwrap();
+        let info = rom.info();
+        assert_eq!(info.format, HeaderFormat::Nes2);
+        assert_eq!(info.mapper, 0);
+        assert_eq!(info.submapper, 3);
+        assert_eq!(info.prg_ram_size, 0x2000);
+        assert_eq!(info.chr_nvram_size, 0x2000);
+        assert_eq!(info.timing, Timing::Pal);
+        assert_eq!(info.expansion_device, 2);
+
+        // 2^14 * 1 bytes of PRG-ROM via the exponent-multiplier form.
+        raw[4] = 14 << 2;
+        raw[9] = 0x0F;
+        assert_eq!(Rom::new(&raw).ok().unwrap().info().prg_rom_size, 0x4000);
+    }
+
+    #[test]
+    fn test_battery_ram() {
+        let mut raw = spin_rom();
+        // STA $6000 before spinning.
+        raw[0x10..0x16].copy_from_slice(&[0x8D, 0x00, 0x60, 0x4C, 0x03, 0x80]);
+        let mut nes = Nes::headless(&raw).unwrap();
+        assert!(nes.battery_ram().is_none());
+
+        raw[6] |= 0b0000_0010;
+        let mut nes = Nes::headless(&raw).unwrap();
+        assert_eq!(nes.battery_ram().unwrap().len(), 0x2000);
+        nes.load_battery_ram(&[0x55; 4]);
+        assert_eq!(nes.battery_ram().unwrap()[..5], [0x55, 0x55, 0x55, 0x55, 0]);
+        assert!(!nes.battery_dirty());
+
+        nes.run_cycles(4);
+        assert!(nes.battery_dirty());
+        assert!(!nes.battery_dirty());
+        assert_eq!(nes.battery_ram().unwrap()[0], 0);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        // Enable NMI, then run nothing but BRKs, each vectoring back to $8010.
+        // The NMI handler spins at $9000.
+        let mut raw = spin_rom();
+        let prg = &mut raw[0x10..0x4010];
+        prg.fill(0x00);
+        prg[..5].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20]);
+        prg[0x1000..0x1003].copy_from_slice(&[0x4C, 0x00, 0x90]);
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x10, 0x80]);
+        let mut nes = Nes::headless(&raw).unwrap();
+
+        // Vblank starts in the middle of some BRK, which then takes the NMI
+        // vector; the NMI isn't serviced a second time.
+        nes.run_cycles(40000);
+        assert!((0x9000..0x9003).contains(&nes.cpu.pc));
+        let stack = 0x0100 + nes.cpu.sp as u16;
+        let status = nes.mmu.read_byte(stack + 1);
+        assert_ne!(status & 0b0001_0000, 0);
+        assert_eq!(nes.mmu.read_word(stack + 2), 0x8012);
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let mut nes = Nes::headless(&spin_rom()).unwrap();
+        assert_eq!(nes.run_cycles(10), 12);
+    }
+
+    #[test]
+    fn test_region_timing() {
+        let mut raw = spin_rom();
+        raw[7] = 0b0000_1000;
+        raw[12] = 0x01;
+        assert_eq!(Nes::headless(&raw).unwrap().region(), Region::Pal);
+
+        for (region, cycles) in [
+            (Region::Ntsc, 29781),
+            (Region::Pal, 33248),
+            (Region::Dendy, 35464),
+        ] {
+            let mut nes = Nes::with_region(&raw, region).unwrap();
+            nes.run_frame(Input::default());
+            let elapsed = nes.run_frame(Input::default()).cycles;
+            assert!(elapsed.abs_diff(cycles) <= 3, "{:?}: {}", region, elapsed);
+        }
+
+        let pal = Nes::with_region(&raw, Region::Pal).unwrap().save_state();
+        let mut ntsc = Nes::with_region(&raw, Region::Ntsc).unwrap();
+        assert_eq!(ntsc.load_state(&pal), Err(StateError::InvalidData));
+    }
+
+    // `pulse_rom` with a loop that counts in $10 and writes the count to the
+    // backdrop colour, so RAM, PPU and APU all move on every frame.
+    fn busy_rom() -> Vec<u8> {
+        let mut raw = pulse_rom();
+        let program = [
+            0xE6, 0x10, 0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, 0xA5, 0x10,
+            0x29, 0x3F, 0x8D, 0x07, 0x20, 0x4C, 0x14, 0x80,
+        ];
+        raw[0x24..0x24 + program.len()].copy_from_slice(&program);
+        raw
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut nes = Nes::headless(&busy_rom()).unwrap();
+        nes.run_frame(Input::default());
+        nes.run_cycles(1234);
+        let saved = nes.save_state();
+        let pc = nes.cpu.pc;
+        let counter = nes.mmu.read_byte(0x10);
+        let frame = nes.run_frame(Input::default()).frame.data.clone();
+
+        for _ in 0..3 {
+            assert_ne!(nes.run_frame(Input::default()).frame.data, frame);
+        }
+        assert_ne!(nes.mmu.read_byte(0x10), counter);
+        nes.load_state(&saved).unwrap();
+        assert_eq!(nes.cpu.pc, pc);
+        assert_eq!(nes.mmu.read_byte(0x10), counter);
+        assert_eq!(nes.save_state(), saved);
+        assert_eq!(nes.run_frame(Input::default()).frame.data, frame);
+    }
+
+    #[test]
+    fn test_load_state_errors() {
+        let mut nes = Nes::headless(&busy_rom()).unwrap();
+        nes.run_frame(Input::default());
+        let saved = nes.save_state();
+
+        let mut other = Nes::headless(&pulse_rom()).unwrap();
+        assert_eq!(other.load_state(&saved), Err(StateError::RomMismatch));
+
+        // A failed load leaves the machine as it was.
+        nes.run_frame(Input::default());
+        let before = nes.save_state();
+        assert_eq!(
+            nes.load_state(&saved[..saved.len() / 2]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(nes.save_state(), before);
+        nes.load_state(&saved).unwrap();
+        assert_eq!(nes.save_state(), saved);
+    }
+
+    #[test]
+    fn test_dmc_playback() {
+        let mut raw = spin_rom();
+        // Play 17 bytes from $C000 at the fastest rate with IRQ on, then spin.
+        let program = [
+            0xA9, 0x8F, 0x8D, 0x10, 0x40, 0xA9, 0x00, 0x8D, 0x12, 0x40, 0xA9, 0x01, 0x8D, 0x13,
+            0x40, 0xA9, 0x10, 0x8D, 0x15, 0x40, 0x4C, 0x14, 0x80,
+        ];
+        raw[0x10..0x10 + program.len()].copy_from_slice(&program);
+        let mut nes = Nes::headless(&raw).unwrap();
+
+        nes.run_cycles(100);
+        assert_eq!(nes.mmu.read_byte(0x4015) & 0b1001_0000, 0b0001_0000);
+        assert!(!nes.irq.is_asserted(IrqSource::Dmc));
+
+        nes.run_cycles(17 * 8 * 54);
+        assert_eq!(nes.mmu.read_byte(0x4015) & 0b1001_0000, 0b1000_0000);
+        assert!(nes.irq.is_asserted(IrqSource::Dmc));
+        nes.mmu.write_byte(0x4015, 0x00);
+        assert!(!nes.irq.is_asserted(IrqSource::Dmc));
+    }
+
+    // Starts a constant-volume square wave on pulse 1, then spins.
+    fn pulse_rom() -> Vec<u8> {
+        let mut raw = spin_rom();
+        let program = [
+            0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0xA9, 0xFD, 0x8D, 0x02,
+            0x40, 0xA9, 0x00, 0x8D, 0x03, 0x40, 0x4C, 0x14, 0x80,
+        ];
+        raw[0x10..0x10 + program.len()].copy_from_slice(&program);
+        raw
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn test_pulse_samples() {
+        let mut nes = Nes::headless(&pulse_rom()).unwrap();
+
+        nes.run_cycles(30000);
+        assert_eq!(nes.mmu.read_byte(0x4015) & 0x01, 0x01);
+        let mut samples = Vec::new();
+        nes.drain_samples(&mut samples);
+        let max = samples.iter().cloned().fold(f32::MIN, f32::max);
+        let min = samples.iter().cloned().fold(f32::MAX, f32::min);
+        assert!(max - min > 0.1);
+        assert!(samples.iter().all(|&sample| sample <= 1.0));
+
+        nes.drain_samples(&mut samples);
+        assert_eq!(samples.len(), 30000 * 44100 / 1789773);
+
+        nes.mmu.write_byte(0x4015, 0x00);
+        assert_eq!(nes.mmu.read_byte(0x4015) & 0x01, 0x00);
+    }
+
+    // PRG is marked with its 8K bank number and CHR with its 1K one; every
+    // 16K of PRG ends in a spin loop and vectors to it.
+    fn mapper_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks];
+        raw.extend([mapper << 4, mapper & 0xF0]);
+        raw.resize(0x10, 0);
+        let mut prg = vec![0xEAu8; prg_banks as usize * 0x4000];
+        for (bank, data) in prg.chunks_mut(0x2000).enumerate() {
+            data[0] = bank as u8;
+        }
+        for data in prg.chunks_mut(0x4000) {
+            data[0x3FF0..0x3FF3].copy_from_slice(&[0x4C, 0xF0, 0xFF]);
+            data[0x3FFC..0x3FFE].copy_from_slice(&[0xF0, 0xFF]);
+        }
+        raw.extend(prg);
+        let mut chr = vec![0u8; chr_banks as usize * 0x2000];
+        for (bank, data) in chr.chunks_mut(0x400).enumerate() {
+            data[0] = bank as u8;
+        }
+        raw.extend(chr);
+        raw
+    }
+
+    fn chr_byte(nes: &Nes, address: u16) -> u8 {
+        match PpuHandler::read(&*nes.rom.borrow(), address) {
+            memory::MemoryRead::Value(value) => value,
+            memory::MemoryRead::Pass => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_discrete_mappers() {
+        // CNROM, with the ROM's $EA pulling bit 0 of the write low.
+        let mut nes = Nes::headless(&mapper_rom(3, 2, 4)).unwrap();
+        nes.mmu.write_byte(0x8001, 0x03);
+        assert_eq!(chr_byte(&nes, 0x0000), 24);
+        nes.set_bus_conflicts(true);
+        nes.mmu.write_byte(0x8001, 0x03);
+        assert_eq!(chr_byte(&nes, 0x0000), 16);
+
+        let mut nes = Nes::headless(&mapper_rom(7, 8, 0)).unwrap();
+        assert_eq!(nes.rom.borrow().nametable(1), Nametable::Ciram(0));
+        nes.mmu.write_byte(0x8000, 0x12);
+        assert_eq!(nes.mmu.read_byte(0x8000), 8);
+        assert_eq!(nes.rom.borrow().nametable(0), Nametable::Ciram(1));
+
+        let mut nes = Nes::headless(&mapper_rom(66, 4, 4)).unwrap();
+        nes.mmu.write_byte(0x8000, 0x11);
+        assert_eq!(nes.mmu.read_byte(0x8000), 4);
+        assert_eq!(chr_byte(&nes, 0x0000), 8);
+
+        let mut nes = Nes::headless(&mapper_rom(11, 4, 4)).unwrap();
+        nes.mmu.write_byte(0x8000, 0x21);
+        assert_eq!(nes.mmu.read_byte(0x8000), 4);
+        assert_eq!(chr_byte(&nes, 0x0000), 16);
+
+        let mut nes = Nes::headless(&mapper_rom(34, 4, 0)).unwrap();
+        nes.mmu.write_byte(0x8000, 0x01);
+        assert_eq!(nes.mmu.read_byte(0x8000), 4);
+
+        // NINA-001, told apart from BNROM by its CHR-ROM.
+        let mut nes = Nes::headless(&mapper_rom(34, 4, 2)).unwrap();
+        nes.mmu.write_byte(0x7FFD, 0x01);
+        nes.mmu.write_byte(0x7FFE, 0x03);
+        nes.mmu.write_byte(0x7FFF, 0x02);
+        assert_eq!(nes.mmu.read_byte(0x8000), 4);
+        assert_eq!(chr_byte(&nes, 0x0000), 12);
+        assert_eq!(chr_byte(&nes, 0x1000), 8);
+        assert_eq!(nes.mmu.read_byte(0x7FFE), 0x03);
+
+        // Forcing conflicts on a board with real registers does nothing.
+        let mut nes = Nes::headless(&mapper_rom(4, 4, 4)).unwrap();
+        nes.set_bus_conflicts(true);
+        assert!(!nes.rom.borrow().info().bus_conflicts);
+    }
+
+    #[test]
+    fn test_vrc6() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x08, 0x01, 0x80, 0x10];
+        raw.resize(0x10, 0);
+        let mut prg = vec![0xEAu8; 0x20000];
+        for bank in 0..16 {
+            prg[bank * 0x2000] = bank as u8;
+        }
+        prg[0x1E001..0x1E004].copy_from_slice(&[0x4C, 0x01, 0xE0]);
+        prg[0x1FFFC..0x1FFFE].copy_from_slice(&[0x01, 0xE0]);
+        raw.extend(prg);
+        raw.extend(vec![0u8; 0x2000]);
+        let mut nes = Nes::headless(&raw).unwrap();
+
+        nes.mmu.write_byte(0x8000, 0x02);
+        nes.mmu.write_byte(0xC000, 0x03);
+        assert_eq!(nes.mmu.read_byte(0x8000), 4);
+        assert_eq!(nes.mmu.read_byte(0xA000), 5);
+        assert_eq!(nes.mmu.read_byte(0xC000), 3);
+        assert_eq!(nes.mmu.read_byte(0xE000), 15);
+
+        // A square wave on the first expansion pulse.
+        nes.mmu.write_byte(0x9000, 0x7F);
+        nes.mmu.write_byte(0x9001, 0xFF);
+        nes.mmu.write_byte(0x9002, 0x81);
+        let mut samples = Vec::new();
+        nes.run_cycles(30000);
+        nes.drain_samples(&mut samples);
+        assert!(peak(&samples[100..]) > 0.05);
+        nes.set_channel_muted(Channel::Expansion, true);
+        samples.clear();
+        nes.run_cycles(30000);
+        nes.drain_samples(&mut samples);
+        assert!(peak(&samples[400..]) < 0.001);
+
+        // Cycle mode counts up from the latch and fires on overflow.
+        nes.mmu.write_byte(0xF000, 0xF0);
+        nes.mmu.write_byte(0xF001, 0x06);
+        nes.run_cycles(8);
+        assert!(!nes.rom.borrow().irq());
+        nes.run_cycles(16);
+        assert!(nes.rom.borrow().irq());
+        nes.mmu.write_byte(0xF002, 0x00);
+        assert!(!nes.rom.borrow().irq());
+    }
+
+    #[test]
+    fn test_channel_controls() {
+        let mut nes = Nes::headless(&pulse_rom()).unwrap();
+        nes.run_cycles(100);
+        let state = nes.channel_state(Channel::Pulse1);
+        assert_eq!(
+            state,
+            ChannelState {
+                period: 0xFD,
+                volume: 15,
+                duty: 2,
+                length: 10,
+                active: true,
+            }
+        );
+        assert!(!nes.channel_state(Channel::Pulse2).active);
+
+        let mut samples = Vec::new();
+        nes.run_cycles(30000);
+        nes.drain_samples(&mut samples);
+        let loud = peak(&samples[100..]);
+
+        nes.set_channel_volume(Channel::Pulse1, 0.5);
+        samples.clear();
+        nes.run_cycles(30000);
+        nes.drain_samples(&mut samples);
+        let quiet = peak(&samples[100..]);
+        assert!(quiet < loud * 0.6 && quiet > loud * 0.4);
+
+        // Soloing another channel silences pulse 1 as much as muting it.
+        for (solo, muted) in [(true, false), (false, true)] {
+            nes.set_channel_solo(Channel::Triangle, solo);
+            nes.set_channel_muted(Channel::Pulse1, muted);
+            samples.clear();
+            nes.run_cycles(30000);
+            nes.drain_samples(&mut samples);
+            assert!(peak(&samples[200..]) < 0.001);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use core::f32::consts::PI;
+
+// libm isn't available without std; the filters only need these at setup.
+pub fn sin_cos(angle: f32) -> (f32, f32) {
+    let mut x = angle % (2.0 * PI);
+    if x > PI {
+        x -= 2.0 * PI;
+    } else if x < -PI {
+        x += 2.0 * PI;
+    }
+    // Taylor series, good to about 1e-4 over [-pi, pi].
+    let (mut sin, mut cos) = (0.0, 0.0);
+    let (mut term_sin, mut term_cos) = (x, 1.0);
+    for n in 1..9 {
+        sin += term_sin;
+        cos += term_cos;
+        let n = n as f32;
+        term_sin *= -x * x / ((2.0 * n) * (2.0 * n + 1.0));
+        term_cos *= -x * x / ((2.0 * n - 1.0) * (2.0 * n));
+    }
+    (sin, cos)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sin_cos() {
+        for deg in [-270.0, -90.0, 0.0, 30.0, 180.0, 400.0] {
+            let (sin, cos) = sin_cos(deg * PI / 180.0);
+            let (expected_sin, expected_cos) = match deg as i32 {
+                -270 => (1.0, 0.0),
+                -90 => (-1.0, 0.0),
+                0 => (0.0, 1.0),
+                30 => (0.5, 0.866),
+                180 => (0.0, -1.0),
+                _ => (0.643, 0.766),
+            };
+            assert!((sin - expected_sin).abs() < 1e-3);
+            assert!((cos - expected_cos).abs() < 1e-3);
+        }
+    }
+}
@@ -3,6 +3,8 @@ use alloc::{rc::Rc, vec::Vec};
 use hashbrown::HashMap;
 use libc_print::libc_println;
 
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub trait Bus {
     fn read_byte(&self, address: u16) -> u8;
     fn read_word(&self, address: u16) -> u16 {
@@ -84,6 +86,16 @@ impl Bus for MemoryBus {
     }
 }
 
+impl Snapshot for MemoryBus {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.memory[..0x0800]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.memory[..0x0800])
+    }
+}
+
 pub enum MemoryRead {
     Value(u8),
     Pass,
@@ -102,7 +114,6 @@ pub trait MemoryHandler {
 
 #[cfg(test)]
 mod test {
-    use crate::cartrige::Mirroring;
     use crate::ppu::Ppu;
     use crate::Device;
 
@@ -119,7 +130,7 @@ mod test {
     fn test_mem_read_write_to_ppu() {
         let mut mmu = MemoryBus::new();
 
-        let ppu = Device::new(Ppu::new(vec![0; 0x2000], Mirroring::Horizontal, false));
+        let ppu = Device::new(Ppu::new_empty_rom());
 
         mmu.register((0x2000, 0x3FFF), ppu.handler());
         mmu.register((0x4014, 0x4014), ppu.handler());
@@ -137,8 +148,9 @@ mod test {
         assert_eq!(ppu.borrow().oam_data[0xFE], 0xFE);
         assert_eq!(ppu.borrow().oam_addr_reg, 0xFF);
 
+        // The high byte only lands in v together with the low byte.
         mmu.write_byte(0x2006, 0xFE);
-        assert_eq!(ppu.borrow().addr_reg.get(), 0x3E00);
+        assert_eq!(ppu.borrow().addr_reg.get(), 0x0000);
         mmu.write_byte(0x2006, 0xFE);
         assert_eq!(ppu.borrow().addr_reg.get(), 0x3EFE);
     }
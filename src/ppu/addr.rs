@@ -1,5 +1,11 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// The PPU's internal v/t/x/w registers. $2005 and $2006 share the write
+// latch and both go through the temporary address before landing in v.
 pub struct AddressRegister {
     value: u16,
+    temp: u16,
+    fine_x: u8,
     hi_ptr: bool,
 }
 
@@ -7,6 +13,8 @@ impl AddressRegister {
     pub fn new() -> Self {
         Self {
             value: 0,
+            temp: 0,
+            fine_x: 0,
             hi_ptr: true,
         }
     }
@@ -18,26 +26,100 @@ impl AddressRegister {
     pub fn update(&mut self, data: u8) {
         let data = data as u16;
         if self.hi_ptr {
-            self.value = data << 8 | (self.value & 0xFF);
+            self.temp = (data & 0x3F) << 8 | (self.temp & 0xFF);
         } else {
-            self.value = data | (self.value & 0xFF00);
+            self.temp = data | (self.temp & 0xFF00);
+            self.value = self.temp;
         }
 
-        self.value &= 0x3FFF;
+        self.hi_ptr = !self.hi_ptr;
+    }
+
+    pub fn scroll(&mut self, data: u8) {
+        let data = data as u16;
+        if self.hi_ptr {
+            self.temp = (self.temp & !0x001F) | data >> 3;
+            self.fine_x = (data & 0x07) as u8;
+        } else {
+            self.temp = (self.temp & !0x73E0) | (data & 0x07) << 12 | (data & 0xF8) << 2;
+        }
 
         self.hi_ptr = !self.hi_ptr;
     }
 
+    pub fn nametable(&mut self, data: u8) {
+        self.temp = (self.temp & !0x0C00) | ((data & 0x03) as u16) << 10;
+    }
+
     pub fn increment(&mut self, inc: u8) {
         self.value = self.value.wrapping_add(inc as u16);
         self.value &= 0x3FFF;
     }
 
+    pub fn increment_x(&mut self) {
+        if self.value & 0x001F == 31 {
+            self.value &= !0x001F;
+            self.value ^= 0x0400;
+        } else {
+            self.value += 1;
+        }
+    }
+
+    pub fn increment_y(&mut self) {
+        if self.value & 0x7000 != 0x7000 {
+            self.value += 0x1000;
+            return;
+        }
+        self.value &= !0x7000;
+        let coarse_y = match (self.value & 0x03E0) >> 5 {
+            29 => {
+                self.value ^= 0x0800;
+                0
+            }
+            31 => 0,
+            coarse_y => coarse_y + 1,
+        };
+        self.value = (self.value & !0x03E0) | coarse_y << 5;
+    }
+
+    pub fn copy_x(&mut self) {
+        self.value = (self.value & !0x041F) | (self.temp & 0x041F);
+    }
+
+    pub fn copy_y(&mut self) {
+        self.value = (self.value & !0x7BE0) | (self.temp & 0x7BE0);
+    }
+
     pub fn reset_latch(&mut self) {
         self.hi_ptr = true;
     }
 
     pub fn get(&self) -> u16 {
-        self.value
+        self.value & 0x3FFF
+    }
+
+    pub fn fine_x(&self) -> u8 {
+        self.fine_x
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        self.value >> 12 & 0x07
+    }
+}
+
+impl Snapshot for AddressRegister {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u16(self.value);
+        state.write_u16(self.temp);
+        state.write_u8(self.fine_x);
+        state.write_bool(self.hi_ptr);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.value = state.read_u16()? & 0x7FFF;
+        self.temp = state.read_u16()? & 0x7FFF;
+        self.fine_x = state.read_u8()? & 0x07;
+        self.hi_ptr = state.read_bool()?;
+        Ok(())
     }
 }
@@ -1,7 +1,5 @@
 use bitflags::bitflags;
 
-use crate::cartridge::Mirroring;
-
 use super::TileSize;
 
 bitflags! {
@@ -62,21 +60,6 @@ impl ControllRegister {
         }
     }
 
-    pub fn nametable(&self, mirroring: Mirroring) -> u16 {
-        (match (mirroring, self.bits() & 0x03) {
-            (Mirroring::Vertical, 0)
-            | (Mirroring::Vertical, 2)
-            | (Mirroring::Horizontal, 0)
-            | (Mirroring::Horizontal, 1) => 0,
-            (Mirroring::Vertical, 1)
-            | (Mirroring::Vertical, 3)
-            | (Mirroring::Horizontal, 2)
-            | (Mirroring::Horizontal, 3) => 1,
-            (_, nametable) => nametable,
-        }) as u16
-            * 0x400
-    }
-
     pub fn generate_vblank_nmi(&self) -> bool {
         self.contains(ControllRegister::GENERATE_NMI)
     }
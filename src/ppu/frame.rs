@@ -3,8 +3,14 @@ use alloc::vec::Vec;
 pub const WIDTH: usize = 256;
 pub const HEIGHT: usize = 240;
 
+// Each pixel is a 9-bit index into the 512-colour output palette: the low six
+// bits are the palette RAM entry (already masked by greyscale) and bits 6-8
+// are the PPUMASK emphasis bits in red/green/blue order.
+pub const EMPHASIS_SHIFT: u16 = 6;
+
+#[derive(Clone)]
 pub struct Frame {
-    pub data: [u8; WIDTH * HEIGHT],
+    pub data: [u16; WIDTH * HEIGHT],
 }
 
 impl Frame {
@@ -14,10 +20,18 @@ impl Frame {
         }
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, color: u8) {
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u16) {
         let base = y * WIDTH + x;
         if base < self.data.len() {
             self.data[base] = color;
         }
     }
+
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        (self.data[y * WIDTH + x] & 0x3F) as u8
+    }
+
+    pub fn emphasis(&self, x: usize, y: usize) -> u8 {
+        (self.data[y * WIDTH + x] >> EMPHASIS_SHIFT) as u8 & 0x07
+    }
 }
@@ -21,4 +21,17 @@ impl MaskRegister {
     pub fn update(&mut self, data: u8) {
         *self = MaskRegister::from_bits_truncate(data);
     }
+
+    // Greyscale forces the hue bits to zero, leaving the grey column.
+    pub fn color_mask(&self) -> u8 {
+        if self.contains(MaskRegister::GRAYSCALE) {
+            0x30
+        } else {
+            0x3F
+        }
+    }
+
+    pub fn emphasis(&self) -> u8 {
+        self.bits() >> 5
+    }
 }
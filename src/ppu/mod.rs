@@ -1,23 +1,30 @@
-use alloc::vec::Vec;
 use libc_print::libc_println;
 
 use crate::{
-    cartridge::Mirroring,
+    cartridge::Nametable,
     device::{DevHandler, IOHandler},
     memory::{Bus, MemoryBus, MemoryRead, MemoryWrite},
+    region::Region,
+    state::{Snapshot, StateError, StateReader, StateWriter},
     Rom,
 };
 
 use self::{
-    addr::AddressRegister, control::ControllRegister, frame::Frame, mask::MaskRegister,
-    scroll::ScrollRegister, status::StatusRegister,
+    addr::AddressRegister,
+    control::ControllRegister,
+    frame::Frame,
+    mask::MaskRegister,
+    render::{Background, Sprite},
+    status::StatusRegister,
 };
 
 mod addr;
 mod control;
 pub mod frame;
 mod mask;
-mod scroll;
+pub mod ntsc;
+pub mod palette;
+mod render;
 mod status;
 
 #[derive(Clone, Copy)]
@@ -26,15 +33,11 @@ pub enum TileSize {
     Tile16,
 }
 
-pub enum Tile {
-    Tile8(Vec<u8>),
-    Tile16(Vec<u8>),
-}
-
 pub trait PpuHandler {
-    fn tile(&self, idx: usize, size: TileSize) -> Tile;
     fn read(&self, address: u16) -> MemoryRead;
     fn write(&mut self, address: u16, value: u8) -> MemoryWrite;
+    fn nametable(&self, table: u8) -> Nametable;
+    fn bus(&mut self, address: u16);
 }
 
 pub struct Ppu {
@@ -42,13 +45,11 @@ pub struct Ppu {
     pub palette_table: [u8; 32],
     pub vram: [u8; 2048],
     pub oam_data: [u8; 256],
-    pub mirroring: Mirroring,
 
     pub(crate) addr_reg: AddressRegister,
     pub(crate) ctrl_reg: ControllRegister,
     pub(crate) mask_reg: MaskRegister,
     pub(crate) status_reg: StatusRegister,
-    pub(crate) scroll_reg: ScrollRegister,
     pub(crate) oam_addr_reg: u8,
 
     internal_data_buf: u8,
@@ -57,56 +58,76 @@ pub struct Ppu {
     nmi_interrupt: bool,
     dma_enable: bool,
 
+    background: Background,
+    // Only the first 32 bytes and 8 slots exist on hardware; the rest hold
+    // sprites past the limit when `sprite_limit` is off.
+    secondary_oam: [u8; 256],
+    sprites: [Sprite; 64],
+    sprite_count: usize,
+    sprite_zero_line: bool,
+    sprite_limit: bool,
+    bus_addr: u16,
+
     frame: Frame,
     frame_tick: bool,
     ignore_nmi: bool,
-}
 
-#[derive(Debug)]
-pub struct Rect {
-    x1: usize,
-    y1: usize,
-    x2: usize,
-    y2: usize,
+    region: Region,
+    // CPU cycles not yet turned into dots, for PAL's 3.2 ratio.
+    clock_remainder: usize,
 }
 
-impl Rect {
-    pub const fn new(x1: usize, y1: usize, x2: usize, y2: usize) -> Self {
-        Self { x1, y1, x2, y2 }
+impl Ppu {
+    #[cfg(test)]
+    pub fn new_empty_rom() -> Self {
+        Ppu::new_test_rom(crate::cartridge::Mirroring::Horizontal)
     }
 
-    pub fn is_inside(&self, x: usize, y: usize) -> bool {
-        x >= self.x1 && x < self.x2 && y >= self.y1 && y < self.y2
+    // NROM with CHR-RAM, so tests can poke pattern data through $2007.
+    #[cfg(test)]
+    pub fn new_test_rom(mirroring: crate::cartridge::Mirroring) -> Self {
+        use crate::{cartridge::Mirroring, device::Device};
+        use alloc::vec;
+
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x00];
+        match mirroring {
+            Mirroring::Vertical => raw[6] |= 0b0000_0001,
+            Mirroring::FourScreen => raw[6] |= 0b0000_1000,
+            _ => {}
+        }
+        raw.resize(0x10 + 0x4000, 0);
+        let rom = Device::new(Rom::new(&raw).ok().unwrap());
+        Ppu::new(rom.handler(), Region::Ntsc)
     }
-}
-
-impl Ppu {
-    // #[cfg(test)]
-    // pub fn new_empty_rom() -> Self {
-    //     Ppu::new(vec![0; 2048], Mirroring::Horizontal, false)
-    // }
 
-    pub fn new(rom: DevHandler<Rom>, mirroring: Mirroring) -> Self {
+    pub fn new(rom: DevHandler<Rom>, region: Region) -> Self {
         Self {
             rom,
             palette_table: [0; 32],
             vram: [0; 2048],
             oam_data: [0; 256],
-            mirroring,
             addr_reg: AddressRegister::new(),
             ctrl_reg: ControllRegister::new(),
             mask_reg: MaskRegister::new(),
             status_reg: StatusRegister::new(),
-            scroll_reg: ScrollRegister::new(),
             oam_addr_reg: 0,
             internal_data_buf: 0,
             cycles: 0,
             scanline: 0,
             nmi_interrupt: false,
+            background: Background::new(),
+            secondary_oam: [0xFF; 256],
+            sprites: [Sprite::new(); 64],
+            sprite_count: 0,
+            sprite_zero_line: false,
+            sprite_limit: true,
+            bus_addr: 0,
             frame: Frame::new(),
             dma_enable: false,
             frame_tick: false,
             ignore_nmi: false,
+            region,
+            clock_remainder: 0,
         }
     }
 
@@ -123,14 +144,13 @@ impl Ppu {
                 }
                 result
             }
-            0x2000..0x3000 => {
+            0x2000..0x3F00 => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.internal_data_buf = self.nametable_read(addr);
                 result
             }
-            0x3000..0x3F00 => panic!("[PPU] Attempt to Unused Space"),
             0x3F00..0x4000 => {
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr - 0x1000) as usize];
+                self.internal_data_buf = self.nametable_read(addr);
                 self.palette_table[((addr - 0x3F00) % 0x20) as usize]
             }
             _ => panic!("unexpected mirror addr {}", addr),
@@ -148,8 +168,7 @@ impl Ppu {
                     panic!("[PPU] Attempt to Read-Only Space");
                 }
             }
-            0x2000..0x3000 => self.vram[self.mirror_vram_addr(addr) as usize] = value,
-            0x3000..0x3F00 => panic!("[PPU] Access to Unused Space"),
+            0x2000..0x3F00 => self.nametable_write(addr, value),
             0x3F10 | 0x3F14 | 0x3F18 | 0x3F1C => {
                 let mirrored_addr = addr - 0x10;
                 self.palette_table[(mirrored_addr - 0x3F00) as usize] = value;
@@ -165,251 +184,110 @@ impl Ppu {
         }
     }
 
-    fn mirror_vram_addr(&self, address: u16) -> u16 {
-        let mirrored_addr = address & 0x2FFF;
-        let vram_idx = mirrored_addr - 0x2000;
-        let named_table = vram_idx / 0x400;
-        match (&self.mirroring, named_table) {
-            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_idx - 0x800,
-            (Mirroring::Horizontal, 2) => vram_idx - 0x400,
-            (Mirroring::Horizontal, 1) => vram_idx - 0x400,
-            (Mirroring::Horizontal, 3) => vram_idx - 0x800,
-            _ => vram_idx,
+    // $2000-$3EFF, with the cartridge deciding where each table lives.
+    fn nametable_read(&self, address: u16) -> u8 {
+        let address = 0x2000 | (address & 0x0FFF);
+        match self.rom.nametable((address >> 10 & 0x03) as u8) {
+            Nametable::Ciram(page) => self.vram[ciram_index(page, address)],
+            Nametable::Cartridge => match PpuHandler::read(&self.rom, address) {
+                MemoryRead::Value(value) => value,
+                // Nothing drives the bus; it still holds the last byte read.
+                MemoryRead::Pass => self.internal_data_buf,
+            },
         }
     }
 
-    pub fn step(&mut self, cpu_cycles: u16) -> bool {
-        self.cycles += cpu_cycles as usize * 3;
-        if self.cycles >= 341 {
-            if self.is_sprite_0_hit(self.cycles) {
-                self.status_reg.set_sprite_0_hit(true);
+    fn nametable_write(&mut self, address: u16, value: u8) {
+        let address = 0x2000 | (address & 0x0FFF);
+        match self.rom.nametable((address >> 10 & 0x03) as u8) {
+            Nametable::Ciram(page) => self.vram[ciram_index(page, address)] = value,
+            Nametable::Cartridge => {
+                PpuHandler::write(&mut self.rom, address, value);
             }
-            self.scanline += 1;
-            self.cycles = self.cycles - 341;
+        }
+    }
 
-            if self.scanline == 241 {
+    // Returns true when the picture is complete, i.e. vblank has just begun.
+    pub fn step(&mut self, cpu_cycles: u16) -> bool {
+        let (dots, cycles) = self.region.ppu_ratio();
+        let clock = cpu_cycles as usize * dots + self.clock_remainder;
+        self.clock_remainder = clock % cycles;
+        let mut vblank = false;
+        for _ in 0..clock / cycles {
+            vblank |= self.tick();
+        }
+        vblank
+    }
+
+    fn tick(&mut self) -> bool {
+        let rendering = self.rendering();
+        let dot = self.cycles;
+        let prerender = self.region.scanlines() - 1;
+        let mut vblank = false;
+        match (self.scanline, dot) {
+            (0..=239, _) => {
+                if rendering {
+                    self.fetch(dot, false);
+                }
+                if (1..=256).contains(&dot) {
+                    self.output_pixel(dot - 1);
+                }
+            }
+            (line, 1) if line == self.region.vblank_line() => {
                 self.status_reg.set_vblank(!self.ignore_nmi);
-                self.status_reg.set_sprite_0_hit(false);
                 if self.ctrl_reg.generate_vblank_nmi() {
                     self.nmi_interrupt = !self.ignore_nmi;
                 }
+                vblank = true;
             }
-
-            if self.scanline >= 262 {
-                self.scanline = 0;
-                self.status_reg.set_vblank(false);
-                self.status_reg.set_sprite_0_hit(false);
-                self.nmi_interrupt = false;
-                self.ignore_nmi = false;
-                if self.frame_tick
-                    && (self.mask_reg.contains(MaskRegister::SPRITE)
-                        || self.mask_reg.contains(MaskRegister::BACKGROUND))
-                {
-                    self.cycles += 1
+            (line, _) if line == prerender => {
+                if dot == 1 {
+                    self.status_reg.set_vblank(false);
+                    self.status_reg.set_sprite_0_hit(false);
+                    self.status_reg.set_sprite_overflow(false);
+                    self.nmi_interrupt = false;
+                    self.ignore_nmi = false;
+                }
+                if rendering {
+                    self.fetch(dot, true);
                 }
-                self.frame_tick = !self.frame_tick;
-                return true;
             }
+            _ => {}
         }
-        false
-    }
 
-    pub fn render(&mut self) -> &Frame {
-        let offset_x = self.scroll_reg.x as usize;
-        let offset_y = self.scroll_reg.y as usize;
-        let (first_bg, second_bg) = if self.ctrl_reg.nametable(self.mirroring) == 0 {
-            (0, 0x400)
+        if rendering && (self.scanline < 240 || self.scanline == prerender) {
+            self.rom.bus(self.bus_addr);
         } else {
-            (0x400, 0)
-        };
-
-        for x in 0..frame::WIDTH {
-            for y in 0..frame::HEIGHT {
-                self.frame.set_pixel(x, y, self.palette_table[0]);
-            }
+            self.rom.bus(0x2000);
         }
 
-        if self.mask_reg.contains(MaskRegister::SPRITE) {
-            self.render_sprite(false);
-        }
-
-        if self.mask_reg.contains(MaskRegister::BACKGROUND) {
-            self.render_bg(
-                first_bg,
-                &Rect::new(offset_x, offset_y, frame::WIDTH, frame::HEIGHT),
-                -(offset_x as isize),
-                -(offset_y as isize),
-            );
-
-            if offset_x > 0 {
-                self.render_bg(
-                    second_bg,
-                    &Rect::new(0, 0, offset_x, frame::HEIGHT),
-                    (frame::WIDTH - offset_x) as isize,
-                    0,
-                );
-            } else if offset_y > 0 {
-                self.render_bg(
-                    second_bg,
-                    &Rect::new(0, 0, frame::WIDTH, offset_y),
-                    0,
-                    (frame::HEIGHT - offset_y) as isize,
-                );
-            }
-
-            if self.mask_reg.contains(MaskRegister::SPRITE) {
-                for x in 0..frame::WIDTH {
-                    for y in 0..(self.oam_data[0] as usize + 8) {
-                        self.frame.set_pixel(x, y, self.palette_table[0]);
-                    }
-                }
-                self.render_bg(
-                    0,
-                    &Rect::new(0, 0, frame::WIDTH, self.oam_data[0] as usize + 8),
-                    0,
-                    0,
-                );
-            }
-        }
-        if self.mask_reg.contains(MaskRegister::SPRITE) {
-            self.render_sprite(true);
+        self.cycles += 1;
+        // Odd NTSC frames drop the last dot of the pre-render line.
+        if self.scanline == prerender
+            && self.cycles == 340
+            && self.frame_tick
+            && rendering
+            && self.region.skips_odd_dot()
+        {
+            self.cycles += 1;
         }
-        &self.frame
-    }
-
-    fn render_bg(&mut self, nametable_base: u16, range: &Rect, offset_x: isize, offset_y: isize) {
-        let background_bank = self.ctrl_reg.background_pattern_addr();
-
-        for i in 0..0x03C0 {
-            let tile = self.vram[(nametable_base + i) as usize] as u16;
-            let tile_x = i % 32;
-            let tile_y = i / 32;
-            let tile_base = (background_bank + tile * 16) as usize;
-            let tile = self.rom.tile(tile_base, TileSize::Tile8);
-
-            let palette = self.bg_palette(nametable_base, tile_x as usize, tile_y as usize);
-
-            match tile {
-                Tile::Tile8(tile) => {
-                    for y in 0..8 {
-                        let mut lower = tile[y];
-                        let mut upper = tile[y + 8];
-                        for x in (0..8).rev() {
-                            let color_idx = (upper & 1) << 1 | (lower & 1);
-                            upper = upper >> 1;
-                            lower = lower >> 1;
-
-                            let color = palette[color_idx as usize];
-                            let pixel_x = tile_x as usize * 8 + x;
-                            let pixel_y = tile_y as usize * 8 + y;
-
-                            if color_idx != 0 && range.is_inside(pixel_x, pixel_y) {
-                                self.frame.set_pixel(
-                                    (pixel_x as isize + offset_x) as usize,
-                                    (pixel_y as isize + offset_y) as usize,
-                                    color,
-                                );
-                            }
-                        }
-                    }
-                }
-                Tile::Tile16(_) => todo!(),
+        if self.cycles > 340 {
+            self.cycles = 0;
+            self.scanline += 1;
+            if self.scanline > prerender {
+                self.scanline = 0;
+                self.frame_tick = !self.frame_tick;
             }
         }
+        vblank
     }
 
-    fn render_sprite(&mut self, priority: bool) {
-        let sprite_bank = self.ctrl_reg.sprite_pattern_addr();
-        let tile_size = self.ctrl_reg.sprite_size();
-
-        for sprite_idx in (0..self.oam_data.len()).step_by(4).rev() {
-            let sprite_y = self.oam_data[sprite_idx];
-            let tile_idx = self.oam_data[sprite_idx + 1] as u16;
-            let sprite_attr = self.oam_data[sprite_idx + 2];
-            let sprite_x = self.oam_data[sprite_idx + 3];
-
-            if (sprite_attr & 0b0010_0000 == 0) == priority {
-                let horizontal_flip = sprite_attr & 0b0100_0000 != 0;
-                let vertical_flip = sprite_attr & 0b1000_0000 != 0;
-
-                // let tile_base = (tile_idx * 16 + sprite_bank) as usize;
-                let tile_base = match tile_size {
-                    TileSize::Tile8 => (tile_idx << 4) + sprite_bank,
-                    TileSize::Tile16 => {
-                        let bank = (tile_idx & 0x01) * 0x1000;
-                        ((tile_idx & 0xFE) << 4) | bank
-                    }
-                } as usize;
-                let tile = self.rom.tile(tile_base, tile_size);
-
-                let palette_idx = sprite_attr & 0b0000_0011;
-                let palette = self.sprite_palette(palette_idx);
-
-                match tile {
-                    Tile::Tile8(tile) => {
-                        for y in 0..8 {
-                            let mut lower = tile[y];
-                            let mut upper = tile[y + 8];
-                            for x in (0..8).rev() {
-                                let color_idx = (upper & 1) << 1 | (lower & 1);
-                                upper = upper >> 1;
-                                lower = lower >> 1;
-                                if color_idx != 0 {
-                                    let color = palette[color_idx as usize];
-                                    self.frame.set_pixel(
-                                        sprite_x as usize + if horizontal_flip { 7 - x } else { x },
-                                        sprite_y as usize + if vertical_flip { 7 - y } else { y },
-                                        color,
-                                    )
-                                }
-                            }
-                        }
-                    }
-                    Tile::Tile16(tile) => {
-                        for y in 0..8 {
-                            let mut lower = tile[y];
-                            let mut upper = tile[y + 8];
-                            for x in (0..8).rev() {
-                                let color_idx = (upper & 1) << 1 | (lower & 1);
-                                upper = upper >> 1;
-                                lower = lower >> 1;
-                                if color_idx != 0 {
-                                    let color = palette[color_idx as usize];
-                                    self.frame.set_pixel(
-                                        sprite_x as usize + if horizontal_flip { 7 - x } else { x },
-                                        sprite_y as usize + if vertical_flip { 7 - y } else { y },
-                                        color,
-                                    )
-                                }
-                            }
-                        }
-                        for y in 8..16 {
-                            let mut lower = tile[y + 8];
-                            let mut upper = tile[y + 16];
-                            for x in (0..8).rev() {
-                                let color_idx = (upper & 1) << 1 | (lower & 1);
-                                upper = upper >> 1;
-                                lower = lower >> 1;
-                                if color_idx != 0 {
-                                    let color = palette[color_idx as usize];
-                                    self.frame.set_pixel(
-                                        sprite_x as usize + if horizontal_flip { 7 - x } else { x },
-                                        sprite_y as usize + if vertical_flip { 15 - y } else { y },
-                                        color,
-                                    )
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.sprite_limit = limit;
     }
 
-    fn is_sprite_0_hit(&self, cycle: usize) -> bool {
-        let y = self.oam_data[0] as u16;
-        let x = self.oam_data[3] as u16;
-        (y == self.scanline) && (x <= cycle as u16) && self.mask_reg.contains(MaskRegister::SPRITE)
+    pub fn frame(&self) -> &Frame {
+        &self.frame
     }
 
     pub fn nmi(&mut self) -> bool {
@@ -426,7 +304,6 @@ impl Ppu {
         let data = self.status_reg.get();
         self.status_reg.set_vblank(false);
         self.addr_reg.reset_latch();
-        self.scroll_reg.reset_latch();
         data
     }
 
@@ -436,37 +313,6 @@ impl Ppu {
         previous
     }
 
-    fn bg_palette(&self, nametable_base: u16, tile_x: usize, tile_y: usize) -> [u8; 4] {
-        let attr_base = (nametable_base + 0x03C0) as usize;
-        let attr_idx = ((tile_x / 4) + (tile_y / 4) * 8) as usize;
-        let attr_byte = self.vram[attr_base + attr_idx] as usize;
-
-        let palette_idx = match (tile_y % 4 / 2, tile_x % 4 / 2) {
-            (0, 0) => attr_byte & 0b11,
-            (0, 1) => (attr_byte >> 2) & 0b11,
-            (1, 0) => (attr_byte >> 4) & 0b11,
-            (1, 1) => attr_byte >> 6,
-            _ => panic!(),
-        };
-        let palette_start = (palette_idx as usize) * 4 + 1;
-        [
-            self.palette_table[0],
-            self.palette_table[palette_start],
-            self.palette_table[palette_start + 1],
-            self.palette_table[palette_start + 2],
-        ]
-    }
-
-    fn sprite_palette(&self, palette_idx: u8) -> [u8; 4] {
-        let palette_start = 0x11 + (palette_idx * 4) as usize;
-        [
-            0,
-            self.palette_table[palette_start],
-            self.palette_table[palette_start + 1],
-            self.palette_table[palette_start + 2],
-        ]
-    }
-
     pub fn cycle(&self) -> usize {
         self.cycles
     }
@@ -476,6 +322,70 @@ impl Ppu {
     }
 }
 
+impl Snapshot for Ppu {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.palette_table);
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.oam_data);
+        self.addr_reg.save(state);
+        state.write_u8(self.ctrl_reg.bits());
+        state.write_u8(self.mask_reg.bits());
+        state.write_u8(self.status_reg.get());
+        state.write_u8(self.oam_addr_reg);
+        state.write_u8(self.internal_data_buf);
+        state.write_u16(self.cycles as u16);
+        state.write_u16(self.scanline);
+        state.write_bool(self.nmi_interrupt);
+        state.write_bool(self.dma_enable);
+        state.write_bool(self.frame_tick);
+        state.write_bool(self.ignore_nmi);
+        self.background.save(state);
+        state.write_bytes(&self.secondary_oam);
+        for sprite in &self.sprites {
+            sprite.save(state);
+        }
+        state.write_u8(self.sprite_count as u8);
+        state.write_bool(self.sprite_zero_line);
+        state.write_u16(self.bus_addr);
+        state.write_u8(self.clock_remainder as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.palette_table)?;
+        state.read_bytes(&mut self.vram)?;
+        state.read_bytes(&mut self.oam_data)?;
+        self.addr_reg.load(state)?;
+        self.ctrl_reg.update(state.read_u8()?);
+        self.mask_reg.update(state.read_u8()?);
+        self.status_reg.update(state.read_u8()?);
+        self.oam_addr_reg = state.read_u8()?;
+        self.internal_data_buf = state.read_u8()?;
+        self.cycles = state.read_u16()? as usize;
+        self.scanline = state.read_u16()?;
+        self.nmi_interrupt = state.read_bool()?;
+        self.dma_enable = state.read_bool()?;
+        self.frame_tick = state.read_bool()?;
+        self.ignore_nmi = state.read_bool()?;
+        self.background.load(state)?;
+        state.read_bytes(&mut self.secondary_oam)?;
+        for sprite in &mut self.sprites {
+            sprite.load(state)?;
+        }
+        self.sprite_count = state.read_u8()? as usize;
+        self.sprite_zero_line = state.read_bool()?;
+        self.bus_addr = state.read_u16()?;
+        self.clock_remainder = state.read_u8()? as usize;
+        if self.cycles > 340
+            || self.scanline >= self.region.scanlines()
+            || self.sprite_count > 64
+            || self.clock_remainder >= self.region.ppu_ratio().1
+        {
+            return Err(StateError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
 impl IOHandler for Ppu {
     fn read(&mut self, mmu: &MemoryBus, address: u16) -> MemoryRead {
         if address >= 0x2000 && address < 0x4000 {
@@ -483,14 +393,15 @@ impl IOHandler for Ppu {
                 2 => {
                     // libc_println!("{}", self.scanline as usize * 341 + self.cycles);
                     let status = self.read_status();
-                    match self.scanline as usize * 341 + self.cycles {
-                        82181 | 82182 => {
+                    let vblank = self.region.vblank_line() as usize * 341;
+                    match (self.scanline as usize * 341 + self.cycles).wrapping_sub(vblank) {
+                        2 | 3 => {
                             // libc_println!("asdf: {:02X}", status);
                             self.ignore_nmi = true;
                             self.status_reg.set_vblank(false);
                             MemoryRead::Value(status | 0b1000_0000)
                         }
-                        82180 => {
+                        1 => {
                             // libc_println!("fdsa: {:04X}", status);
                             self.ignore_nmi = true;
                             self.status_reg.set_vblank(false);
@@ -534,6 +445,7 @@ impl IOHandler for Ppu {
                     // );
                     let before_nmi = self.ctrl_reg.generate_vblank_nmi();
                     self.ctrl_reg.update(value);
+                    self.addr_reg.nametable(value);
                     if !before_nmi
                         && self.ctrl_reg.generate_vblank_nmi()
                         && self.status_reg.vblank()
@@ -556,12 +468,12 @@ impl IOHandler for Ppu {
                     MemoryWrite::Value(value)
                 }
                 5 => {
-                    self.scroll_reg.update(value);
+                    self.addr_reg.scroll(value);
                     // libc_println!(
                     //     "[PPU] Scroll = {:02X} => pos x:{:02X} y:{:02X} [W]",
                     //     value,
-                    //     self.scroll_reg.x,
-                    //     self.scroll_reg.y
+                    //     self.addr_reg.fine_x(),
+                    //     self.addr_reg.get()
                     // );
                     MemoryWrite::Value(value)
                 }
@@ -597,9 +509,13 @@ impl IOHandler for Ppu {
     }
 }
 
+fn ciram_index(page: u8, address: u16) -> usize {
+    (page as usize & 0x01) * 0x400 + (address as usize & 0x3FF)
+}
+
 #[cfg(test)]
 mod test {
-    use crate::device::Device;
+    use crate::{cartridge::Mirroring, device::Device};
 
     use super::*;
 
@@ -685,9 +601,36 @@ mod test {
         assert_eq!(ppu.read_data(), 0x77); //read from b
     }
 
+    #[test]
+    fn test_vram_four_screen() {
+        let mut ppu = Ppu::new_test_rom(Mirroring::FourScreen);
+
+        for (table, value) in [(0x20, 0x11), (0x24, 0x22), (0x28, 0x33), (0x2C, 0x44)] {
+            ppu.addr_reg.update(table);
+            ppu.addr_reg.update(0x05);
+            ppu.write_data(value);
+        }
+        // Only the first two tables are in the console's own VRAM.
+        assert_eq!(ppu.vram[0x0005], 0x11);
+        assert_eq!(ppu.vram[0x0405], 0x22);
+
+        for (table, value) in [(0x20, 0x11), (0x24, 0x22), (0x28, 0x33), (0x2C, 0x44)] {
+            ppu.addr_reg.update(table);
+            ppu.addr_reg.update(0x05);
+            ppu.read_data(); //load into buffer
+            assert_eq!(ppu.read_data(), value);
+        }
+
+        // $3000-$3EFF mirrors the nametables.
+        ppu.addr_reg.update(0x38);
+        ppu.addr_reg.update(0x05);
+        ppu.read_data(); //load into buffer
+        assert_eq!(ppu.read_data(), 0x33);
+    }
+
     #[test]
     fn test_vram_vertical_mirror() {
-        let mut ppu = Ppu::new(vec![0; 2048], Mirroring::Vertical);
+        let mut ppu = Ppu::new_test_rom(Mirroring::Vertical);
 
         ppu.addr_reg.update(0x20);
         ppu.addr_reg.update(0x05);
@@ -785,4 +728,285 @@ mod test {
         }
         assert_eq!(ppu.borrow_mut().oam_addr_reg, 0);
     }
+
+    fn write_vram(ppu: &mut Ppu, address: u16, data: &[u8]) {
+        ppu.addr_reg.update((address >> 8) as u8);
+        ppu.addr_reg.update(address as u8);
+        for &value in data {
+            ppu.write_data(value);
+        }
+    }
+
+    fn run_to_vblank(ppu: &mut Ppu) {
+        while !ppu.step(1) {}
+    }
+
+    fn run_to_scanline(ppu: &mut Ppu, scanline: u16) {
+        while ppu.scanline() != scanline {
+            ppu.step(1);
+        }
+    }
+
+    // Tile 1 is solid colour 1; the backdrop is $0F and colour 1 is $21.
+    fn solid_tile_ppu() -> Ppu {
+        let mut ppu = Ppu::new_empty_rom();
+        write_vram(&mut ppu, 0x0010, &[0xFF; 8]);
+        write_vram(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_vram(&mut ppu, 0x3F11, &[0x16]);
+        ppu
+    }
+
+    #[test]
+    fn test_mid_frame_scroll_split() {
+        let mut ppu = solid_tile_ppu();
+        for row in 0..30 {
+            ppu.vram[row * 32] = 1;
+        }
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(0b0000_1010);
+        run_to_vblank(&mut ppu);
+
+        run_to_scanline(&mut ppu, 120);
+        ppu.addr_reg.scroll(8);
+        ppu.addr_reg.scroll(0);
+        run_to_vblank(&mut ppu);
+
+        let frame = ppu.frame();
+        assert_eq!(frame.data[10 * frame::WIDTH], 0x21);
+        assert_eq!(frame.data[10 * frame::WIDTH + 8], 0x0F);
+        assert_eq!(frame.data[119 * frame::WIDTH], 0x21);
+        assert_eq!(frame.data[122 * frame::WIDTH], 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_pixels_and_sprite_0_hit() {
+        let mut ppu = solid_tile_ppu();
+        ppu.vram[4 * 32 + 2] = 1;
+        ppu.oam_data[..4].copy_from_slice(&[31, 1, 0, 16]);
+        ppu.oam_data[4..].fill(0xFF);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(0b0001_1110);
+        run_to_vblank(&mut ppu);
+        assert!(ppu.status_reg.sprite_0_hit());
+        ppu.vram[4 * 32 + 2] = 0;
+        run_to_vblank(&mut ppu);
+        assert!(!ppu.status_reg.sprite_0_hit());
+
+        let frame = ppu.frame();
+        assert_eq!(frame.data[31 * frame::WIDTH + 16], 0x0F);
+        assert_eq!(frame.data[32 * frame::WIDTH + 16], 0x16);
+        assert_eq!(frame.data[39 * frame::WIDTH + 23], 0x16);
+        assert_eq!(frame.data[40 * frame::WIDTH + 23], 0x0F);
+        assert_eq!(frame.data[32 * frame::WIDTH + 24], 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_limit_and_overflow() {
+        let mut ppu = solid_tile_ppu();
+        ppu.oam_data.fill(0xFF);
+        for idx in 0..9 {
+            ppu.oam_data[idx * 4..idx * 4 + 4].copy_from_slice(&[49, 1, 0, idx as u8 * 8]);
+        }
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(0b0001_0100);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+        assert!(ppu.status_reg.sprite_overflow());
+        assert_eq!(ppu.frame().data[50 * frame::WIDTH + 56], 0x16);
+        assert_eq!(ppu.frame().data[50 * frame::WIDTH + 64], 0x0F);
+
+        ppu.set_sprite_limit(false);
+        run_to_vblank(&mut ppu);
+        assert!(ppu.status_reg.sprite_overflow());
+        assert_eq!(ppu.frame().data[50 * frame::WIDTH + 64], 0x16);
+    }
+
+    #[test]
+    fn test_sprite_overflow_diagonal_scan() {
+        let mut ppu = solid_tile_ppu();
+        ppu.oam_data.fill(0xFF);
+        for idx in 0..8 {
+            ppu.oam_data[idx * 4..idx * 4 + 4].copy_from_slice(&[49, 1, 0, 0]);
+        }
+        // Sprite 8 misses, so sprite 9 is checked through its tile byte.
+        ppu.oam_data[9 * 4 + 1] = 49;
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(0b0001_0100);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+        assert!(ppu.status_reg.sprite_overflow());
+
+        ppu.oam_data[9 * 4 + 1] = 0xFF;
+        ppu.oam_data[9 * 4] = 49;
+        run_to_vblank(&mut ppu);
+        assert!(!ppu.status_reg.sprite_overflow());
+    }
+
+    #[test]
+    fn test_sprite_priority_front_to_back() {
+        let mut ppu = solid_tile_ppu();
+        write_vram(&mut ppu, 0x3F15, &[0x2A]);
+        ppu.vram[4 * 32 + 2] = 1;
+        ppu.oam_data.fill(0xFF);
+        // A background-priority sprite still hides later sprites below it.
+        ppu.oam_data[..4].copy_from_slice(&[31, 1, 0b0010_0000, 16]);
+        ppu.oam_data[4..8].copy_from_slice(&[31, 1, 0b0000_0001, 20]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(0b0001_1110);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+
+        let frame = ppu.frame();
+        assert_eq!(frame.data[32 * frame::WIDTH + 20], 0x21);
+        assert_eq!(frame.data[32 * frame::WIDTH + 24], 0x2A);
+    }
+
+    fn tall_sprite_frame(tile: u8, attr: u8) -> Frame {
+        let mut ppu = solid_tile_ppu();
+        // Only the first row of the top half ($1020) is opaque.
+        write_vram(&mut ppu, 0x1020, &[0xFF]);
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..4].copy_from_slice(&[31, tile, attr, 16]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.ctrl_reg.update(0b0010_0000);
+        ppu.mask_reg.update(0b0001_0100);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+        ppu.frame().clone()
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let frame = tall_sprite_frame(3, 0);
+        assert_eq!(frame.data[32 * frame::WIDTH + 16], 0x16);
+        assert_eq!(frame.data[33 * frame::WIDTH + 16], 0x0F);
+        assert_eq!(frame.data[47 * frame::WIDTH + 16], 0x0F);
+
+        // Vertical flip swaps the halves as well as the rows.
+        let frame = tall_sprite_frame(3, 0b1000_0000);
+        assert_eq!(frame.data[32 * frame::WIDTH + 16], 0x0F);
+        assert_eq!(frame.data[40 * frame::WIDTH + 16], 0x0F);
+        assert_eq!(frame.data[47 * frame::WIDTH + 16], 0x16);
+
+        // Bit 0 of the tile picks the table, not PPUCTRL.
+        let frame = tall_sprite_frame(2, 0);
+        assert_eq!(frame.data[32 * frame::WIDTH + 16], 0x0F);
+    }
+
+    fn solid_background_frame(mask: u8) -> Frame {
+        let mut ppu = solid_tile_ppu();
+        for row in 0..30 {
+            ppu.vram[row * 32..row * 32 + 32].fill(1);
+        }
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..4].copy_from_slice(&[99, 0x10, 0, 0]);
+        write_vram(&mut ppu, 0x0100, &[0xFF; 8]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(mask);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+        ppu.frame().clone()
+    }
+
+    #[test]
+    fn test_mask_left_column_clipping() {
+        let frame = solid_background_frame(0b0001_1110);
+        assert_eq!(frame.data[10 * frame::WIDTH], 0x21);
+        assert_eq!(frame.data[100 * frame::WIDTH], 0x16);
+
+        let frame = solid_background_frame(0b0001_1000);
+        assert_eq!(frame.data[10 * frame::WIDTH + 7], 0x0F);
+        assert_eq!(frame.data[10 * frame::WIDTH + 8], 0x21);
+        assert_eq!(frame.data[100 * frame::WIDTH + 7], 0x0F);
+
+        let frame = solid_background_frame(0b0001_1010);
+        assert_eq!(frame.data[100 * frame::WIDTH + 7], 0x21);
+    }
+
+    #[test]
+    fn test_mask_greyscale_and_emphasis() {
+        let frame = solid_background_frame(0b1010_1011);
+        assert_eq!(frame.color(8, 10), 0x20);
+        assert_eq!(frame.emphasis(8, 10), 0b101);
+        assert_eq!(frame.data[10 * frame::WIDTH + 8], 0x20 | 0b101 << 6);
+
+        // Emphasis applies to the backdrop as well.
+        let frame = solid_background_frame(0b0100_0000);
+        assert_eq!(frame.data[10 * frame::WIDTH + 8], 0x0F | 0b010 << 6);
+    }
+
+    #[test]
+    fn test_region_vblank_line() {
+        for (region, line) in [
+            (Region::Ntsc, 241),
+            (Region::Pal, 241),
+            (Region::Dendy, 291),
+        ] {
+            let mut ppu = Ppu::new_empty_rom();
+            ppu.region = region;
+            run_to_vblank(&mut ppu);
+            assert_eq!(ppu.scanline(), line);
+            run_to_scanline(&mut ppu, region.scanlines() - 1);
+            run_to_scanline(&mut ppu, 0);
+        }
+    }
+
+    #[test]
+    fn test_pal_emphasis_swap() {
+        let mut ppu = Ppu::new_empty_rom();
+        ppu.region = Region::Pal;
+        ppu.mask_reg.update(0b0010_0000);
+        run_to_vblank(&mut ppu);
+        assert_eq!(ppu.frame().emphasis(0, 0), 0b010);
+    }
+
+    fn sprite_0_hit_at(x: u8, mask: u8) -> bool {
+        let mut ppu = solid_tile_ppu();
+        for row in 0..30 {
+            ppu.vram[row * 32..row * 32 + 32].fill(1);
+        }
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..4].copy_from_slice(&[31, 1, 0b0010_0000, x]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(mask);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+        ppu.status_reg.sprite_0_hit()
+    }
+
+    #[test]
+    fn test_sprite_0_hit_edges() {
+        // Background priority doesn't hide the hit.
+        assert!(sprite_0_hit_at(100, 0b0001_1110));
+        assert!(sprite_0_hit_at(0, 0b0001_1110));
+        // Left-column clipping of either layer removes the overlap there.
+        assert!(!sprite_0_hit_at(0, 0b0001_1100));
+        assert!(!sprite_0_hit_at(0, 0b0001_1010));
+        assert!(sprite_0_hit_at(4, 0b0001_1010));
+        // Only pixel 255 overlaps when the sprite starts there.
+        assert!(!sprite_0_hit_at(255, 0b0001_1110));
+        assert!(sprite_0_hit_at(254, 0b0001_1110));
+        // Both layers have to be shown.
+        assert!(!sprite_0_hit_at(100, 0b0001_0110));
+        assert!(!sprite_0_hit_at(100, 0b0000_1110));
+    }
+
+    #[test]
+    fn test_sprite_0_hit_needs_opaque_pixels() {
+        let mut ppu = solid_tile_ppu();
+        // Tile 2 only has its leftmost column set.
+        write_vram(&mut ppu, 0x0020, &[0x80; 8]);
+        ppu.vram[4 * 32 + 2] = 2;
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..4].copy_from_slice(&[31, 2, 0, 17]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.mask_reg.update(0b0001_1110);
+        run_to_vblank(&mut ppu);
+        run_to_vblank(&mut ppu);
+        assert!(!ppu.status_reg.sprite_0_hit());
+
+        ppu.oam_data[3] = 16;
+        run_to_vblank(&mut ppu);
+        assert!(ppu.status_reg.sprite_0_hit());
+    }
 }
@@ -0,0 +1,173 @@
+use core::f32::consts::PI;
+
+use alloc::{vec, vec::Vec};
+
+use crate::math::sin_cos;
+
+use super::{
+    frame::{Frame, HEIGHT, WIDTH},
+    palette::{signal_level, yiq_to_rgb, COS},
+};
+
+// Output pixels per scanline, close to the 8:7 pixel aspect of a real set.
+pub const NTSC_WIDTH: usize = 602;
+
+// Each PPU dot lasts eight of the twelve colour-clock phases, so a scanline
+// of 341 dots starts four phases later than the previous one.
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES: usize = WIDTH * SAMPLES_PER_PIXEL;
+const LINE_PHASE: usize = 341 * SAMPLES_PER_PIXEL % 12;
+
+#[derive(Clone, Copy)]
+pub struct NtscSettings {
+    // -1.0 blurs, 0.0 is a plain 12-sample luma filter, 1.0 lets more of the
+    // chroma carrier through as fringing.
+    pub sharpness: f32,
+    pub saturation: f32,
+    // Degrees added to the decoded chroma phase.
+    pub hue: f32,
+    // Alternate the starting phase every frame like the real PPU does.
+    pub dot_crawl: bool,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        Self {
+            sharpness: 0.0,
+            saturation: 1.0,
+            hue: 0.0,
+            dot_crawl: true,
+        }
+    }
+}
+
+pub struct NtscFilter {
+    settings: NtscSettings,
+    frame_phase: usize,
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> Self {
+        Self {
+            settings,
+            frame_phase: 0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: NtscSettings) {
+        self.settings = settings;
+    }
+
+    // Encodes each scanline as a composite signal and decodes it again,
+    // producing NTSC_WIDTH x HEIGHT RGBA pixels.
+    pub fn apply(&mut self, frame: &Frame) -> Vec<u8> {
+        let settings = self.settings;
+        let luma_width = (12.0 - settings.sharpness.clamp(-1.0, 1.0) * 4.0) as usize;
+        let (sin, cos) = sin_cos(settings.hue * PI / 180.0);
+        let (hue_sin, hue_cos) = (sin * settings.saturation, cos * settings.saturation);
+
+        let mut out = Vec::with_capacity(NTSC_WIDTH * HEIGHT * 4);
+        let mut y_sum = vec![0.0; SAMPLES + 1];
+        let mut i_sum = vec![0.0; SAMPLES + 1];
+        let mut q_sum = vec![0.0; SAMPLES + 1];
+        for line in 0..HEIGHT {
+            let start = (self.frame_phase + line * LINE_PHASE) % 12;
+            let pixels = &frame.data[line * WIDTH..(line + 1) * WIDTH];
+            for sample in 0..SAMPLES {
+                let phase = (start + sample) % 12;
+                let level = signal_level(pixels[sample / SAMPLES_PER_PIXEL], phase);
+                y_sum[sample + 1] = y_sum[sample] + level;
+                i_sum[sample + 1] = i_sum[sample] + level * COS[phase];
+                q_sum[sample + 1] = q_sum[sample] + level * COS[(phase + 9) % 12];
+            }
+
+            for x in 0..NTSC_WIDTH {
+                let center = (2 * x + 1) * SAMPLES / (2 * NTSC_WIDTH);
+                let y = window(&y_sum, center, luma_width);
+                let i = window(&i_sum, center, 24);
+                let q = window(&q_sum, center, 24);
+                let (i, q) = (i * hue_cos - q * hue_sin, i * hue_sin + q * hue_cos);
+                let [r, g, b] = yiq_to_rgb(y, i, q);
+                out.extend_from_slice(&[r, g, b, 0xFF]);
+            }
+        }
+
+        // 262 lines move the next frame four phases on, and the dot skipped
+        // on odd frames takes eight back, so there are only two phases.
+        if settings.dot_crawl {
+            self.frame_phase = (self.frame_phase + 4) % 8;
+        }
+        out
+    }
+}
+
+impl Frame {
+    pub fn to_ntsc_rgba(&self, filter: &mut NtscFilter) -> Vec<u8> {
+        filter.apply(self)
+    }
+}
+
+// Mean of the signal over `width` samples around `center`; the blanking
+// outside the visible line counts as black.
+fn window(sums: &[f32], center: usize, width: usize) -> f32 {
+    let start = center.saturating_sub(width / 2);
+    let end = (center + width.div_ceil(2)).min(SAMPLES);
+    (sums[end] - sums[start]) / width as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::palette::Palette;
+    use super::*;
+
+    fn solid_frame(color: u16) -> Frame {
+        let mut frame = Frame::new();
+        frame.data.fill(color);
+        frame
+    }
+
+    #[test]
+    fn test_flat_colors_match_palette() {
+        let palette = Palette::composite();
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        for color in [0x0F, 0x16, 0x21, 0x2A, 0x30, 0x16 | 1 << 6] {
+            let out = filter.apply(&solid_frame(color));
+            assert_eq!(out.len(), NTSC_WIDTH * HEIGHT * 4);
+            let pixel = (100 * NTSC_WIDTH + 300) * 4;
+            for (&filtered, &expected) in out[pixel..pixel + 3].iter().zip(&palette.rgb(color)) {
+                assert!((filtered as i16 - expected as i16).abs() <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_artifacts_and_dot_crawl() {
+        // A one-dot checkerboard of white and black bleeds colour.
+        let mut frame = Frame::new();
+        for (idx, pixel) in frame.data.iter_mut().enumerate() {
+            *pixel = if idx % 2 == 0 { 0x30 } else { 0x0F };
+        }
+        let mut filter = NtscFilter::new(NtscSettings {
+            sharpness: 1.0,
+            ..NtscSettings::default()
+        });
+        let first = filter.apply(&frame);
+        let second = filter.apply(&frame);
+        let third = filter.apply(&frame);
+        let pixel = (100 * NTSC_WIDTH + 300) * 4;
+        let [r, g, b] = [first[pixel], first[pixel + 1], first[pixel + 2]];
+        assert!(r != g || g != b);
+        assert_ne!(first, second);
+        assert_eq!(first, third);
+
+        let mut filter = NtscFilter::new(NtscSettings {
+            saturation: 0.0,
+            dot_crawl: false,
+            ..NtscSettings::default()
+        });
+        let first = filter.apply(&solid_frame(0x16));
+        assert_eq!(first[pixel], first[pixel + 1]);
+        assert_eq!(first[pixel + 1], first[pixel + 2]);
+        assert_eq!(first, filter.apply(&solid_frame(0x16)));
+    }
+}
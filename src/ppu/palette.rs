@@ -0,0 +1,298 @@
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use super::frame::Frame;
+
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+    BadSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::BadSize(size) => write!(
+                f,
+                "palette must hold 64 or 512 RGB entries ({} bytes given)",
+                size
+            ),
+        }
+    }
+}
+
+// Colour lookup for all 512 index/emphasis combinations a frame can hold.
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    // The 2C02 palette, decoded from the composite signal the PPU generates.
+    pub fn composite() -> Self {
+        Self {
+            colors: (0..512).map(composite_color).collect(),
+        }
+    }
+
+    // The 2C03/2C05 RGB PPUs used in Vs. System and PlayChoice-10 boards.
+    pub fn rgb_ppu() -> Self {
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for &color in RGB_PPU.iter() {
+                // These PPUs drive an emphasised channel to full instead of
+                // darkening the other two.
+                let mut rgb = [(color >> 6) & 7, (color >> 3) & 7, color & 7];
+                for (channel, level) in rgb.iter_mut().enumerate() {
+                    if emphasis & 1 << channel != 0 {
+                        *level = 7;
+                    }
+                }
+                colors.push(rgb.map(|level| (level * 255 / 7) as u8));
+            }
+        }
+        Self { colors }
+    }
+
+    // The palette the example frontend has always shipped with.
+    pub fn classic() -> Self {
+        Self::from_hex(&CLASSIC)
+    }
+
+    // FCEUX's built-in default palette (src/palette.cpp in the FCEUX sources).
+    pub fn fceux() -> Self {
+        Self::from_hex(&FCEUX)
+    }
+
+    // FirebrandX's "Smooth (FBX)" palette, from his NES palette set at
+    // firebrandx.com.
+    pub fn smooth_fbx() -> Self {
+        Self::from_hex(&SMOOTH_FBX)
+    }
+
+    // Raw `.pal` files: 64 RGB triples, or 512 with the emphasis variants
+    // following in emphasis-bit order.
+    pub fn from_pal(data: &[u8]) -> Result<Self, PaletteError> {
+        let colors: Vec<[u8; 3]> = data
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        match data.len() {
+            192 => Ok(Self::expand(&colors)),
+            1536 => Ok(Self { colors }),
+            size => Err(PaletteError::BadSize(size)),
+        }
+    }
+
+    fn from_hex(base: &[u32; 64]) -> Self {
+        Self::expand(&base.map(|color| [(color >> 16) as u8, (color >> 8) as u8, color as u8]))
+    }
+
+    // Emphasis on a 2C02 attenuates the channels that aren't emphasised.
+    fn expand(base: &[[u8; 3]]) -> Self {
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for (idx, color) in base.iter().enumerate() {
+                let mut rgb = *color;
+                // $xE/$xF are black whatever the emphasis.
+                if idx & 0x0E != 0x0E {
+                    for (channel, level) in rgb.iter_mut().enumerate() {
+                        if emphasis != 0 && emphasis & 1 << channel == 0 {
+                            *level = (*level as u16 * 13 / 16) as u8;
+                        }
+                    }
+                }
+                colors.push(rgb);
+            }
+        }
+        Self { colors }
+    }
+
+    pub fn rgb(&self, color: u16) -> [u8; 3] {
+        self.colors[(color & 0x1FF) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::composite()
+    }
+}
+
+impl Frame {
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.data.len() * 4);
+        for &pixel in self.data.iter() {
+            let [r, g, b] = palette.rgb(pixel);
+            out.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+        out
+    }
+
+    pub fn to_rgb565(&self, palette: &Palette) -> Vec<u16> {
+        self.data
+            .iter()
+            .map(|&pixel| {
+                let [r, g, b] = palette.rgb(pixel);
+                (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+            })
+            .collect()
+    }
+}
+
+// Signal levels for the low and high half of the colour wave, per luma level,
+// normalised so that $0D is 0 and $20 is 1 after subtracting black.
+const LOW_LEVELS: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH_LEVELS: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const ATTENUATION: f32 = 0.746;
+
+// cos(n * 30°); the sine is the same table three steps later.
+pub(super) const COS: [f32; 12] = [
+    1.0, 0.866, 0.5, 0.0, -0.5, -0.866, -1.0, -0.866, -0.5, 0.0, 0.5, 0.866,
+];
+
+fn in_phase(hue: usize, phase: usize) -> bool {
+    (hue + phase + 8) % 12 < 6
+}
+
+// The composite level a pixel produces at one of the twelve colour-clock
+// phases, scaled so black is 0 and white is 1.
+pub(super) fn signal_level(pixel: u16, phase: usize) -> f32 {
+    let pixel = pixel as usize;
+    let hue = pixel & 0x0F;
+    let level = if hue > 0x0D { 1 } else { (pixel >> 4) & 0x03 };
+    let emphasis = pixel >> 6 & 0x07;
+    let mut signal = match hue {
+        0x00 => HIGH_LEVELS[level],
+        0x0D.. => LOW_LEVELS[level],
+        _ if in_phase(hue, phase) => HIGH_LEVELS[level],
+        _ => LOW_LEVELS[level],
+    };
+    if (emphasis & 1 != 0 && in_phase(0, phase))
+        || (emphasis & 2 != 0 && in_phase(4, phase))
+        || (emphasis & 4 != 0 && in_phase(8, phase))
+    {
+        signal *= ATTENUATION;
+    }
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+pub(super) fn yiq_to_rgb(y: f32, i: f32, q: f32) -> [u8; 3] {
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+    [
+        to_byte(y + 0.946882 * i + 0.623557 * q),
+        to_byte(y - 0.274788 * i - 0.635691 * q),
+        to_byte(y - 1.108545 * i + 1.709007 * q),
+    ]
+}
+
+fn composite_color(pixel: usize) -> [u8; 3] {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let value = signal_level(pixel as u16, phase) / 12.0;
+        y += value;
+        i += value * COS[phase];
+        q += value * COS[(phase + 9) % 12];
+    }
+    yiq_to_rgb(y, i, q)
+}
+
+// 3 bits per channel, one octal digit each.
+const RGB_PPU: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022,
+    0o000, 0o000, 0o000, 0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140,
+    0o040, 0o053, 0o044, 0o000, 0o000, 0o000, 0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740,
+    0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000, 0o777, 0o567, 0o657, 0o757,
+    0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+const CLASSIC: [u32; 64] = [
+    0x808080, 0x003DA6, 0x0012B0, 0x440096, 0xA1005E, 0xC70028, 0xBA0600, 0x8C1700, 0x5C2F00,
+    0x104500, 0x054A00, 0x00472E, 0x004166, 0x000000, 0x050505, 0x050505, 0xC7C7C7, 0x0077FF,
+    0x2155FF, 0x8237FA, 0xEB2FB5, 0xFF2950, 0xFF2200, 0xD63200, 0xC46200, 0x358000, 0x058F00,
+    0x008A55, 0x0099CC, 0x212121, 0x090909, 0x090909, 0xFFFFFF, 0x0FD7FF, 0x69A2FF, 0xD480FF,
+    0xFF45F3, 0xFF618B, 0xFF8833, 0xFF9C12, 0xFABC20, 0x9FE30E, 0x2BF035, 0x0CF0A4, 0x05FBFF,
+    0x5E5E5E, 0x0D0D0D, 0x0D0D0D, 0xFFFFFF, 0xA6FCFF, 0xB3ECFF, 0xDAABEB, 0xFFA8F9, 0xFFABB3,
+    0xFFD2B0, 0xFFEFA6, 0xFFF79C, 0xD7E895, 0xA6EDAF, 0xA2F2DA, 0x99FFFC, 0xDDDDDD, 0x111111,
+    0x111111,
+];
+
+const FCEUX: [u32; 64] = [
+    0x747474, 0x24188C, 0x0000A8, 0x44009C, 0x8C0074, 0xA80010, 0xA40000, 0x7C0800, 0x402C00,
+    0x004400, 0x005000, 0x003C14, 0x183C5C, 0x000000, 0x000000, 0x000000, 0xBCBCBC, 0x0070EC,
+    0x2038EC, 0x8000F0, 0xBC00BC, 0xE40058, 0xD82800, 0xC84C0C, 0x887000, 0x009400, 0x00A800,
+    0x009038, 0x008088, 0x000000, 0x000000, 0x000000, 0xFCFCFC, 0x3CBCFC, 0x5C94FC, 0xCC88FC,
+    0xF478FC, 0xFC74B4, 0xFC7460, 0xFC9838, 0xF0BC3C, 0x80D010, 0x4CDC48, 0x58F898, 0x00E8D8,
+    0x787878, 0x000000, 0x000000, 0xFCFCFC, 0xA8E4FC, 0xC4D4FC, 0xD4C8FC, 0xFCC4FC, 0xFCC4D8,
+    0xFCBCB0, 0xFCD8A8, 0xFCE4A0, 0xE0FCA0, 0xA8F0BC, 0xB0FCCC, 0x9CFCF0, 0xC4C4C4, 0x000000,
+    0x000000,
+];
+
+const SMOOTH_FBX: [u32; 64] = [
+    0x6A6D6A, 0x001380, 0x1E008A, 0x39007A, 0x550056, 0x5A0018, 0x4F1000, 0x3D1C00, 0x253200,
+    0x003D00, 0x004000, 0x003924, 0x002E55, 0x000000, 0x000000, 0x000000, 0xB9BCB9, 0x1850C7,
+    0x4B30E3, 0x7322D6, 0x951FA9, 0x9D285C, 0x983700, 0x7F4C00, 0x5E6400, 0x227700, 0x027E02,
+    0x007645, 0x006E8A, 0x000000, 0x000000, 0x000000, 0xFFFFFF, 0x68A6FF, 0x8C9CFF, 0xB586FF,
+    0xD975FD, 0xE377B9, 0xE58D68, 0xD49D29, 0xB3AF0C, 0x7BC211, 0x55CA47, 0x46CB81, 0x47C1C5,
+    0x4A4D4A, 0x000000, 0x000000, 0xFFFFFF, 0xCCEAFF, 0xDDDEFF, 0xECDAFF, 0xF8D7FE, 0xFCD6F5,
+    0xFDDBCF, 0xF9E7B5, 0xF1F0AA, 0xDAFAA9, 0xC9FFBC, 0xC3FBD7, 0xC4F6F6, 0xBEC1BE, 0x000000,
+    0x000000,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pal_file_sizes() {
+        let base = [0x40u8; 192];
+        let palette = Palette::from_pal(&base).ok().unwrap();
+        assert_eq!(palette.rgb(0x05), [0x40; 3]);
+        // Red emphasis darkens green and blue.
+        assert_eq!(palette.rgb(0x05 | 1 << 6), [0x40, 0x34, 0x34]);
+        assert_eq!(palette.rgb(0x0F | 7 << 6), [0x40; 3]);
+
+        let mut full = [0u8; 1536];
+        full[1533..].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(Palette::from_pal(&full).ok().unwrap().rgb(0x1FF), [1, 2, 3]);
+
+        assert_eq!(
+            Palette::from_pal(&[0; 100]).err(),
+            Some(PaletteError::BadSize(100))
+        );
+    }
+
+    #[test]
+    fn test_builtin_palettes() {
+        let composite = Palette::composite();
+        assert_eq!(composite.rgb(0x0F), [0, 0, 0]);
+        assert_eq!(composite.rgb(0x20), [255, 255, 255]);
+        assert_eq!(composite.rgb(0x30), [255, 255, 255]);
+        let [r, g, b] = composite.rgb(0x16);
+        assert!(r > g && r > b);
+        let [r, g, b] = composite.rgb(0x30 | 4 << 6);
+        assert!(b > r && b > g);
+
+        let rgb = Palette::rgb_ppu();
+        assert_eq!(rgb.rgb(0x20), [255, 255, 255]);
+        assert_eq!(rgb.rgb(0x0D | 1 << 6), [255, 0, 0]);
+        assert_eq!(Palette::classic().rgb(0x01), [0x00, 0x3D, 0xA6]);
+        assert_eq!(Palette::fceux().rgb(0x16), [0xD8, 0x28, 0x00]);
+        assert_eq!(Palette::smooth_fbx().rgb(0x30), [0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_frame_conversion() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, 0x20);
+        let palette = Palette::rgb_ppu();
+        let rgba = frame.to_rgba(&palette);
+        assert_eq!(rgba.len(), 256 * 240 * 4);
+        assert_eq!(&rgba[4..8], &[255, 255, 255, 255]);
+        let rgb565 = frame.to_rgb565(&palette);
+        assert_eq!(rgb565[1], 0xFFFF);
+        assert_eq!(rgb565[0], 0x6B6D);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{
+    device::DevHandler,
+    memory::MemoryRead,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+    Rom,
+};
+
+use super::{frame::EMPHASIS_SHIFT, mask::MaskRegister, Ppu, PpuHandler, TileSize};
+
+// Tile data for the next two background tiles, 16-bit shifters clocked once
+// per dot with the upper byte feeding the pixel at `fine_x`.
+pub struct Background {
+    next_tile: u8,
+    next_attr: u8,
+    next_lo: u8,
+    next_hi: u8,
+    pattern_lo: u16,
+    pattern_hi: u16,
+    attr_lo: u16,
+    attr_hi: u16,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Self {
+            next_tile: 0,
+            next_attr: 0,
+            next_lo: 0,
+            next_hi: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+            attr_lo: 0,
+            attr_hi: 0,
+        }
+    }
+
+    fn load(&mut self) {
+        self.pattern_lo = (self.pattern_lo & 0xFF00) | self.next_lo as u16;
+        self.pattern_hi = (self.pattern_hi & 0xFF00) | self.next_hi as u16;
+        let attr_lo = if self.next_attr & 0b01 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        let attr_hi = if self.next_attr & 0b10 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        self.attr_lo = (self.attr_lo & 0xFF00) | attr_lo;
+        self.attr_hi = (self.attr_hi & 0xFF00) | attr_hi;
+    }
+
+    fn shift(&mut self) {
+        self.pattern_lo <<= 1;
+        self.pattern_hi <<= 1;
+        self.attr_lo <<= 1;
+        self.attr_hi <<= 1;
+    }
+
+    // (pixel, palette)
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let bit = 0x8000 >> fine_x;
+        let pick = |shifter: u16| (shifter & bit != 0) as u8;
+        (
+            pick(self.pattern_hi) << 1 | pick(self.pattern_lo),
+            pick(self.attr_hi) << 1 | pick(self.attr_lo),
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Sprite {
+    pattern_lo: u8,
+    pattern_hi: u8,
+    attr: u8,
+    x: u8,
+}
+
+impl Sprite {
+    pub const fn new() -> Self {
+        Self {
+            pattern_lo: 0,
+            pattern_hi: 0,
+            attr: 0,
+            x: 0,
+        }
+    }
+}
+
+struct SpritePixel {
+    pixel: u8,
+    palette: u8,
+    behind: bool,
+    zero: bool,
+}
+
+impl Ppu {
+    pub(super) fn rendering(&self) -> bool {
+        self.mask_reg.contains(MaskRegister::SPRITE)
+            || self.mask_reg.contains(MaskRegister::BACKGROUND)
+    }
+
+    fn chr(&self, address: u16) -> u8 {
+        match <DevHandler<Rom> as PpuHandler>::read(&self.rom, address) {
+            MemoryRead::Value(value) => value,
+            MemoryRead::Pass => 0,
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.bus_addr = address;
+        self.chr(address)
+    }
+
+    fn read_nametable(&mut self, address: u16) -> u8 {
+        self.bus_addr = address;
+        self.nametable_read(address)
+    }
+
+    // One dot of the fetch pipeline on a visible or pre-render scanline.
+    pub(super) fn fetch(&mut self, dot: usize, prerender: bool) {
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.background.shift();
+        }
+
+        let v = self.addr_reg.get();
+        match dot {
+            1..=256 | 321..=337 => match (dot - 1) % 8 {
+                0 => {
+                    self.background.load();
+                    self.background.next_tile = self.read_nametable(0x2000 | (v & 0x0FFF));
+                }
+                2 => {
+                    let address = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let shift = (v >> 4) & 0x04 | v & 0x02;
+                    self.background.next_attr = self.read_nametable(address) >> shift & 0x03;
+                }
+                4 => {
+                    let address = self.background_pattern();
+                    self.background.next_lo = self.read_chr(address);
+                }
+                6 => {
+                    let address = self.background_pattern() + 8;
+                    self.background.next_hi = self.read_chr(address);
+                }
+                7 => self.addr_reg.increment_x(),
+                _ => {}
+            },
+            339 => {
+                self.read_nametable(0x2000 | (v & 0x0FFF));
+            }
+            _ => {}
+        }
+
+        if dot == 256 {
+            self.addr_reg.increment_y();
+        }
+        if dot == 257 {
+            self.background.load();
+            self.addr_reg.copy_x();
+        }
+        if prerender && (280..=304).contains(&dot) {
+            self.addr_reg.copy_y();
+        }
+
+        if (257..=320).contains(&dot) {
+            self.oam_addr_reg = 0;
+            if dot == 257 {
+                if prerender {
+                    self.sprite_count = 0;
+                    self.sprite_zero_line = false;
+                } else {
+                    self.evaluate_sprites();
+                }
+            }
+            self.fetch_sprite(dot - 257);
+        }
+    }
+
+    fn background_pattern(&self) -> u16 {
+        self.ctrl_reg.background_pattern_addr()
+            + (self.background.next_tile as u16) * 16
+            + self.addr_reg.fine_y()
+    }
+
+    fn sprite_height(&self) -> u16 {
+        match self.ctrl_reg.sprite_size() {
+            TileSize::Tile8 => 8,
+            TileSize::Tile16 => 16,
+        }
+    }
+
+    fn sprite_in_range(&self, y: u8) -> bool {
+        self.scanline.wrapping_sub(y as u16) < self.sprite_height()
+    }
+
+    // Copies the sprites on the next scanline into secondary OAM. Hardware
+    // stops at eight; without the limit the rest spill into the extra slots.
+    fn evaluate_sprites(&mut self) {
+        let limit = if self.sprite_limit { 8 } else { 64 };
+        let mut overflow_from = None;
+        self.sprite_count = 0;
+        self.sprite_zero_line = false;
+        for idx in 0..64 {
+            if !self.sprite_in_range(self.oam_data[idx * 4]) {
+                continue;
+            }
+            if self.sprite_count == limit {
+                break;
+            }
+            let slot = self.sprite_count * 4;
+            self.secondary_oam[slot..slot + 4]
+                .copy_from_slice(&self.oam_data[idx * 4..idx * 4 + 4]);
+            self.sprite_zero_line |= idx == 0;
+            self.sprite_count += 1;
+            if self.sprite_count == 8 {
+                overflow_from = Some(idx + 1);
+            }
+        }
+
+        // Once secondary OAM is full the PPU keeps looking for a ninth sprite,
+        // but a miss advances the byte offset along with the sprite index, so
+        // it compares tile, attribute and X bytes as if they were Y.
+        if let Some(mut n) = overflow_from {
+            let mut m = 0;
+            while n < 64 {
+                if self.sprite_in_range(self.oam_data[n * 4 + m]) {
+                    self.status_reg.set_sprite_overflow(true);
+                    break;
+                }
+                n += 1;
+                m = (m + 1) & 0x03;
+            }
+        }
+    }
+
+    fn sprite_entry(&self, slot: usize) -> [u8; 4] {
+        if slot < self.sprite_count {
+            let base = slot * 4;
+            [
+                self.secondary_oam[base],
+                self.secondary_oam[base + 1],
+                self.secondary_oam[base + 2],
+                self.secondary_oam[base + 3],
+            ]
+        } else {
+            [0xFF; 4]
+        }
+    }
+
+    // Dots 257-320 fetch the pattern rows of the evaluated sprites, eight
+    // dots per slot; empty slots still fetch tile $FF.
+    fn fetch_sprite(&mut self, offset: usize) {
+        let slot = offset / 8;
+        let v = self.addr_reg.get();
+        match offset % 8 {
+            0 | 2 => self.bus_addr = 0x2000 | (v & 0x0FFF),
+            4 => self.bus_addr = self.sprite_pattern(self.sprite_entry(slot)),
+            6 => self.bus_addr += 8,
+            7 => self.load_sprite(slot),
+            _ => {}
+        }
+        // Slots past the hardware's eight never appear on the bus.
+        if offset == 63 {
+            for slot in 8..self.sprite_count {
+                self.load_sprite(slot);
+            }
+        }
+    }
+
+    fn load_sprite(&mut self, slot: usize) {
+        if slot >= self.sprite_count {
+            self.sprites[slot] = Sprite::new();
+            return;
+        }
+        let entry = self.sprite_entry(slot);
+        let address = self.sprite_pattern(entry);
+        let (mut lo, mut hi) = (self.chr(address), self.chr(address + 8));
+        if entry[2] & 0b0100_0000 != 0 {
+            lo = lo.reverse_bits();
+            hi = hi.reverse_bits();
+        }
+        self.sprites[slot] = Sprite {
+            pattern_lo: lo,
+            pattern_hi: hi,
+            attr: entry[2],
+            x: entry[3],
+        };
+    }
+
+    fn sprite_pattern(&self, entry: [u8; 4]) -> u16 {
+        let height = self.sprite_height();
+        let mut row = self.scanline.wrapping_sub(entry[0] as u16) % height;
+        if entry[2] & 0b1000_0000 != 0 {
+            row = height - 1 - row;
+        }
+        let tile = entry[1] as u16;
+        match self.ctrl_reg.sprite_size() {
+            TileSize::Tile8 => self.ctrl_reg.sprite_pattern_addr() + tile * 16 + row,
+            TileSize::Tile16 => {
+                let bank = (tile & 0x01) * 0x1000;
+                let tile = (tile & 0xFE) + row / 8;
+                bank + tile * 16 + row % 8
+            }
+        }
+    }
+
+    fn sprite_pixel(&self, x: usize) -> Option<SpritePixel> {
+        for (idx, sprite) in self.sprites[..self.sprite_count].iter().enumerate() {
+            let column = x.wrapping_sub(sprite.x as usize);
+            if column >= 8 {
+                continue;
+            }
+            let bit = 7 - column;
+            let pixel = (sprite.pattern_hi >> bit & 1) << 1 | (sprite.pattern_lo >> bit & 1);
+            if pixel != 0 {
+                return Some(SpritePixel {
+                    pixel,
+                    palette: sprite.attr & 0b11,
+                    behind: sprite.attr & 0b0010_0000 != 0,
+                    zero: idx == 0 && self.sprite_zero_line,
+                });
+            }
+        }
+        None
+    }
+
+    pub(super) fn output_pixel(&mut self, x: usize) {
+        let color = if self.rendering() {
+            let left = x < 8;
+            let show_background = self.mask_reg.contains(MaskRegister::BACKGROUND)
+                && (!left || self.mask_reg.contains(MaskRegister::BACKGROUND_LEFT));
+            let show_sprite = self.mask_reg.contains(MaskRegister::SPRITE)
+                && (!left || self.mask_reg.contains(MaskRegister::SPRITE_LEFT));
+            let (pixel, palette) = if show_background {
+                self.background.pixel(self.addr_reg.fine_x())
+            } else {
+                (0, 0)
+            };
+            let sprite = if show_sprite {
+                self.sprite_pixel(x)
+            } else {
+                None
+            };
+            let index = match (pixel, sprite) {
+                (0, None) => 0,
+                (0, Some(sprite)) => 0x10 | sprite.palette << 2 | sprite.pixel,
+                (_, None) => palette << 2 | pixel,
+                (_, Some(sprite)) => {
+                    // Both pixels are opaque after clipping; the last column
+                    // never reports a hit.
+                    if sprite.zero && x != 255 {
+                        self.status_reg.set_sprite_0_hit(true);
+                    }
+                    if sprite.behind {
+                        palette << 2 | pixel
+                    } else {
+                        0x10 | sprite.palette << 2 | sprite.pixel
+                    }
+                }
+            };
+            self.palette_table[index as usize]
+        } else {
+            // With rendering off, pointing v into palette RAM shows that entry.
+            let v = self.addr_reg.get();
+            if v >= 0x3F00 {
+                self.palette_table[(v & 0x1F) as usize]
+            } else {
+                self.palette_table[0]
+            }
+        };
+        let mut emphasis = self.mask_reg.emphasis();
+        if self.region.swaps_emphasis() {
+            emphasis = emphasis & 0b100 | (emphasis & 0b001) << 1 | (emphasis & 0b010) >> 1;
+        }
+        let color =
+            (color & self.mask_reg.color_mask()) as u16 | (emphasis as u16) << EMPHASIS_SHIFT;
+        self.frame.set_pixel(x, self.scanline as usize, color);
+    }
+}
+
+impl Snapshot for Background {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.next_tile);
+        state.write_u8(self.next_attr);
+        state.write_u8(self.next_lo);
+        state.write_u8(self.next_hi);
+        state.write_u16(self.pattern_lo);
+        state.write_u16(self.pattern_hi);
+        state.write_u16(self.attr_lo);
+        state.write_u16(self.attr_hi);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.next_tile = state.read_u8()?;
+        self.next_attr = state.read_u8()? & 0x03;
+        self.next_lo = state.read_u8()?;
+        self.next_hi = state.read_u8()?;
+        self.pattern_lo = state.read_u16()?;
+        self.pattern_hi = state.read_u16()?;
+        self.attr_lo = state.read_u16()?;
+        self.attr_hi = state.read_u16()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sprite {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.pattern_lo);
+        state.write_u8(self.pattern_hi);
+        state.write_u8(self.attr);
+        state.write_u8(self.x);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pattern_lo = state.read_u8()?;
+        self.pattern_hi = state.read_u8()?;
+        self.attr = state.read_u8()?;
+        self.x = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -44,4 +44,12 @@ impl StatusRegister {
             self.0 & 0b1011_1111
         }
     }
+
+    pub fn set_sprite_overflow(&mut self, flag: bool) {
+        self.0 = if flag {
+            self.0 | 0b0010_0000
+        } else {
+            self.0 & 0b1101_1111
+        }
+    }
 }
@@ -0,0 +1,117 @@
+use crate::cartridge::Timing;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // The common Famiclone: PAL video with NTSC-style APU timing.
+    Dendy,
+}
+
+impl Region {
+    // Multi-region carts run as NTSC, which is what most of them target.
+    pub fn from_timing(timing: Timing) -> Self {
+        match timing {
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+        }
+    }
+
+    pub fn cpu_clock(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1789773.0,
+            Region::Pal => 1662607.0,
+            Region::Dendy => 1773448.0,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    // PPU dots per CPU cycle as a fraction; PAL runs 3.2 dots per cycle.
+    pub(crate) fn ppu_ratio(&self) -> (usize, usize) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    pub(crate) fn scanlines(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Dendy keeps NTSC's vblank length and puts the extra lines before it.
+    pub(crate) fn vblank_line(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub(crate) fn skips_odd_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    // The 2C07 and Dendy PPUs wire the red and green emphasis bits the other
+    // way round.
+    pub(crate) fn swaps_emphasis(&self) -> bool {
+        *self != Region::Ntsc
+    }
+
+    // Quarter-frame steps in CPU cycles and the sequence length.
+    pub(crate) fn frame_counter(&self, mode5: bool) -> ([usize; 4], usize) {
+        match (self, mode5) {
+            (Region::Pal, false) => ([8313, 16627, 24939, 33252], 33253),
+            (Region::Pal, true) => ([8313, 16627, 24939, 41565], 41566),
+            (_, false) => ([7457, 14913, 22371, 29829], 29830),
+            (_, true) => ([7457, 14913, 22371, 37281], 37282),
+        }
+    }
+
+    pub(crate) fn noise_periods(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Pal => &NOISE_PERIODS_PAL,
+            _ => &NOISE_PERIODS_NTSC,
+        }
+    }
+
+    pub(crate) fn dmc_rates(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Pal => &DMC_RATES_PAL,
+            _ => &DMC_RATES_NTSC,
+        }
+    }
+
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+            Region::Dendy => 2,
+        }
+    }
+}
+
+const NOISE_PERIODS_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+const NOISE_PERIODS_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+const DMC_RATES_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+const DMC_RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
@@ -0,0 +1,217 @@
+use core::fmt;
+
+use alloc::vec::Vec;
+
+const STATE_MAGIC: [u8; 4] = [0x52, 0x4E, 0x53, 0x1A];
+// Only bumped when a released format changes.
+pub const STATE_VERSION: u16 = 1;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch,
+    Truncated,
+    InvalidData,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a RustyNES save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                version, STATE_VERSION
+            ),
+            StateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::InvalidData => write!(f, "save state contains invalid data"),
+        }
+    }
+}
+
+pub trait Snapshot {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(checksum: u32) -> Self {
+        let mut writer = Self { data: Vec::new() };
+        writer.write_bytes(&STATE_MAGIC);
+        writer.write_u16(STATE_VERSION);
+        writer.write_u32(checksum);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    // Length-prefixed, for buffers whose size depends on the cartridge.
+    pub fn write_slice(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.write_bytes(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8], checksum: u32) -> Result<Self, StateError> {
+        let mut reader = Self { data, pos: 0 };
+        let mut magic = [0u8; 4];
+        reader
+            .read_bytes(&mut magic)
+            .map_err(|_| StateError::BadMagic)?;
+        if magic != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.read_u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if reader.read_u32()? != checksum {
+            return Err(StateError::RomMismatch);
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() - self.pos < len {
+            return Err(StateError::Truncated);
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidData),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0u8; 2];
+        self.read_bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0u8; 4];
+        self.read_bytes(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0u8; 8];
+        self.read_bytes(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, value: &mut [u8]) -> Result<(), StateError> {
+        value.copy_from_slice(self.take(value.len())?);
+        Ok(())
+    }
+
+    pub fn read_slice(&mut self, value: &mut [u8]) -> Result<(), StateError> {
+        if self.read_u32()? as usize != value.len() {
+            return Err(StateError::InvalidData);
+        }
+        self.read_bytes(value)
+    }
+
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.pos == self.data.len() {
+            Ok(())
+        } else {
+            Err(StateError::InvalidData)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut writer = StateWriter::new(0xDEAD_BEEF);
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_slice(&[1, 2, 3]);
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data, 0xDEAD_BEEF).unwrap();
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        let mut slice = [0u8; 3];
+        assert_eq!(reader.read_slice(&mut slice), Ok(()));
+        assert_eq!(slice, [1, 2, 3]);
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_state_header_rejected() {
+        let data = StateWriter::new(1).finish();
+        assert_eq!(
+            StateReader::new(&data, 2).err(),
+            Some(StateError::RomMismatch)
+        );
+        assert_eq!(
+            StateReader::new(&data[..3], 1).err(),
+            Some(StateError::BadMagic)
+        );
+
+        let mut newer = data.clone();
+        newer[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            StateReader::new(&newer, 1).err(),
+            Some(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+
+        let mut reader = StateReader::new(&data, 1).unwrap();
+        assert_eq!(reader.read_u8(), Err(StateError::Truncated));
+    }
+}